@@ -7,19 +7,23 @@ use cgmath::{
 use is_odd::IsOdd;
 use itertools::Itertools;
 use rand::{random, Rng};
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::f64::consts::PI;
 use std::fmt::{Debug, Display, Formatter};
-use std::fs::OpenOptions;
-use std::io::{BufWriter, Write};
+use std::fs;
 use std::ops::{Mul, Sub};
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
+#[cfg(feature = "bevy")]
 use bevy::prelude::Reflect;
 use log::warn;
 use truck_base::bounding_box::BoundingBox;
 use truck_base::cgmath64::{Point3, Vector3};
 use truck_stepio::out;
+use crate::algo::machine::{AxisLimits, MachineConfig};
+use crate::algo::material::Material;
+use crate::algo::tooling::{stack_changes, DieStack};
 
 
 
@@ -27,6 +31,36 @@ const L: i32 = 0;
 const R: i32 = 1;
 const A: i32 = 2;
 const K: i32 = 3;
+pub const MIN_BEND_ANGLE_DEG: f64 = 0.01;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImportError {
+    Io(String),
+    Format(String),
+    BadNumber { line: usize, value: String },
+    NotEnoughColumns { line: usize, expected: usize, found: usize },
+    NotEnoughPoints(usize),
+    ZeroLengthSegment(usize),
+    ReversedSegment(usize),
+    TangentsOverlap(usize),
+    MissingClr(usize),
+}
+impl Display for ImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Io(e) => write!(f, "Unable to read file: {}", e),
+            ImportError::Format(e) => write!(f, "Invalid file format: {}", e),
+            ImportError::BadNumber { line, value } => write!(f, "Line {}: '{}' is not a number", line, value),
+            ImportError::NotEnoughColumns { line, expected, found } => write!(f, "Line {}: expected {} columns, found {}", line, expected, found),
+            ImportError::NotEnoughPoints(qty) => write!(f, "At least 2 points are required, found {}", qty),
+            ImportError::ZeroLengthSegment(i) => write!(f, "Points {} and {} coincide", i, i + 1),
+            ImportError::ReversedSegment(i) => write!(f, "Pipe turns back by 180 deg at point {}", i),
+            ImportError::TangentsOverlap(i) => write!(f, "Bend tangents overlap on segment {}, CLR too big or points too close", i),
+            ImportError::MissingClr(i) => write!(f, "Point {} is a bend but has no CLR", i),
+        }
+    }
+}
+impl std::error::Error for ImportError {}
 
 pub enum AnimStatus {
     Enabled,
@@ -96,7 +130,8 @@ impl Debug for AnimState {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Copy,  Reflect)]
+#[derive(Debug, Clone, PartialEq, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "bevy", derive(Reflect))]
 pub struct LRACLR {
     pub id1: i32,
     pub id2: i32,
@@ -400,6 +435,141 @@ fn generate_tor_by_2pts(id: u64, sp: Point3, ep: Point3, radius: f64, fwd_dir_s:
 
     tor
 }
+//Splits a text table row, ";" and tab separated rows may use decimal commas
+pub fn split_table_row(line: &str) -> Vec<String> {
+    let fields: Vec<&str> = if (line.contains(';')) {
+        line.split(';').collect()
+    } else if (line.contains('\t')) {
+        line.split('\t').collect()
+    } else {
+        line.split(|c: char| c == ',' || c.is_whitespace()).filter(|f| !f.is_empty()).collect()
+    };
+    fields.iter().map(|f| f.trim().replace(',', ".")).collect()
+}
+//Numeric rows of a text table, header and empty lines are skipped. Line numbers start from 1
+pub fn parse_number_rows(text: &str) -> Result<Vec<(usize, Vec<f64>)>, ImportError> {
+    let mut rows: Vec<(usize, Vec<f64>)> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let fields = split_table_row(line);
+        let mut values: Vec<f64> = vec![];
+        let mut is_header = false;
+        for (j, field) in fields.iter().enumerate() {
+            if (field.is_empty()) {
+                continue;
+            }
+            match field.parse::<f64>() {
+                Ok(v) => values.push(v),
+                Err(_) => {
+                    if (rows.is_empty() && values.is_empty() && j == 0) {
+                        is_header = true;
+                        break;
+                    }
+                    return Err(ImportError::BadNumber { line: i + 1, value: field.clone() });
+                }
+            }
+        }
+        if (!is_header && !values.is_empty()) {
+            rows.push((i + 1, values));
+        }
+    }
+    Ok(rows)
+}
+//X;Y;Z;CLR per line, CLR of the first and the last point is ignored
+pub fn parse_xyz(text: &str) -> Result<Vec<(Point3, f64)>, ImportError> {
+    let mut pts: Vec<(Point3, f64)> = vec![];
+    for (line, values) in parse_number_rows(text)? {
+        if (values.len() < 3) {
+            return Err(ImportError::NotEnoughColumns { line: line, expected: 4, found: values.len() });
+        }
+        let clr = if (values.len() > 3) { values[3] } else { 0.0 };
+        pts.push((Point3::new(values[0], values[1], values[2]), clr));
+    }
+    Ok(pts)
+}
+pub fn load_xyz(path: &PathBuf, pipe_radius: f64) -> Result<Vec<LRACLR>, ImportError> {
+    let text = fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))?;
+    let pts = parse_xyz(&text)?;
+    xyz_to_lraclr(&pts, pipe_radius)
+}
+//Inverse of cnc_to_poly: virtual sharps with CLR per vertex to LRA rows
+pub fn xyz_to_lraclr(xyz_clr: &Vec<(Point3, f64)>, pipe_radius: f64) -> Result<Vec<LRACLR>, ImportError> {
+    if (xyz_clr.len() < 2) {
+        return Err(ImportError::NotEnoughPoints(xyz_clr.len()));
+    }
+    //Straight-through vertices carry no bend
+    let mut pts: Vec<(Point3, f64)> = vec![xyz_clr[0].clone()];
+    for i in 1..xyz_clr.len() {
+        let (p, clr) = xyz_clr[i];
+        let prev = pts.last().unwrap().0;
+        if (p.distance(prev) < TOLE) {
+            return Err(ImportError::ZeroLengthSegment(i - 1));
+        }
+        if (i < xyz_clr.len() - 1) {
+            let next = xyz_clr[i + 1].0;
+            let d_in = p.sub(prev).normalize();
+            let d_out = next.sub(p).normalize();
+            let angle = Deg::from(d_in.angle(d_out)).0;
+            if (angle < MIN_BEND_ANGLE_DEG) {
+                continue;
+            }
+            if (180.0 - angle < MIN_BEND_ANGLE_DEG) {
+                return Err(ImportError::ReversedSegment(i));
+            }
+            if (clr <= 0.0) {
+                return Err(ImportError::MissingClr(i));
+            }
+        }
+        pts.push((p, clr));
+    }
+    let n = pts.len();
+    let dirs: Vec<Vector3> = (0..n - 1).map(|i| pts[i + 1].0.sub(pts[i].0).normalize()).collect();
+    let mut angles: Vec<f64> = vec![0.0; n];
+    let mut tangents: Vec<f64> = vec![0.0; n];
+    for i in 1..n - 1 {
+        let angle = dirs[i - 1].angle(dirs[i]).0;
+        angles[i] = angle;
+        tangents[i] = pts[i].1 * (angle / 2.0).tan();
+    }
+
+    let mut ret: Vec<LRACLR> = vec![];
+    let mut prev_plane: Option<Vector3> = None;
+    let mut counter = 0;
+    for k in 0..n - 1 {
+        let l = pts[k].0.distance(pts[k + 1].0) - tangents[k] - tangents[k + 1];
+        if (l < 0.0) {
+            return Err(ImportError::TangentsOverlap(k));
+        }
+        let mut lra = LRACLR {
+            id1: counter,
+            id2: counter + 1,
+            l: l,
+            r: 0.0,
+            a: 0.0,
+            clr: 0.0,
+            pipe_radius: pipe_radius,
+        };
+        counter = counter + 2;
+        if (k + 1 < n - 1) {
+            let frwd = dirs[k];
+            let bend_plane = frwd.cross(dirs[k + 1]).normalize();
+            //Rotation about forward that brings the previous bend plane to this one, same as cnc_to_poly
+            let rot = match prev_plane {
+                None => 0.0,
+                Some(pp) => {
+                    let phi = pp.cross(bend_plane).dot(frwd).atan2(pp.dot(bend_plane));
+                    Deg::from(Rad(phi)).0 / ROT_DIR_CCW
+                }
+            };
+            prev_plane = Some(bend_plane);
+            lra.r = rot;
+            lra.a = Deg::from(Rad(angles[k + 1])).0;
+            lra.clr = pts[k + 1].1;
+        }
+        ret.push(lra);
+    }
+    optimize_lraclr(&mut ret);
+    Ok(ret)
+}
 pub fn optimize_lraclr(lraclrs: &mut Vec<LRACLR>) {
     lraclrs.iter_mut().for_each(|lracl| {
         if (abs(lracl.r) >= 360.0) {
@@ -449,108 +619,6 @@ pub fn all_to_stp(cyls: &Vec<MainCylinder>, tors: &Vec<BendToro>) -> Vec<u8> {
 
 
 
-pub fn byt(
-    t: f64,
-    cmnd: &Vec<LRACLR>,
-    up_dir: &Vector3
-) -> (Point3, Vector3, Vector3, Vector3, f64, u64, Option<Point3>, f64, f64, f64) {
-
-    let len: f64 = tot_pipe_len(cmnd);
-    let dist = len * t;
-
-
-    let (circles, tors) = cnc_to_poly(cmnd, up_dir);
-    let indexes = circles.len() + tors.len();
-
-
-    let mut x_dir = Vector3::new(1.0, 0.0, 0.0);
-    let mut y_dir = Vector3::new(0.0, 1.0, 0.0);
-    let mut z_dir = Vector3::new(0.0, 0.0, 1.0);
-    let mut pt: Point3 = Point3::new(0.0, 0.0, 0.0);
-    let mut rot_deg = 0.0;
-    let mut cp: Option<Point3> = None;
-    let mut theta: f64 = 0.0;
-
-    let mut fragment_len = 0.0;
-    let mut id: u64 = 0;
-    let mut bend_radius = 0.0;
-
-    for i in (0..indexes).step_by(2) {
-
-        if let Some(c) = circles.iter().find(|cil| cil.id == i as u64) {
-            let min = fragment_len;
-            fragment_len += c.h;
-            let max = fragment_len;
-
-
-            if dist >= min && dist < max {
-                id = c.id;
-                cp = None;
-                rot_deg = LRACLR::rotate_by_id(c.id as i32, cmnd);
-                let offset = dist - min;
-
-                pt = c.ca.loc + c.ca.dir * offset;
-
-
-                let pt2: Point3 = c.ca.loc + c.ca.dir * (offset + 1.0);
-                x_dir = pt2 - pt; // Вектор вперед
-
-
-                if let Some(arc) = tors.iter().find(|tor| tor.id == (i + 1) as u64) {
-                    let pt2 = pt + arc.bend_plane_norm.normalize();
-                    z_dir = pt - pt2;
-                    y_dir = z_dir.cross(x_dir);
-                } else if let Some(arc) = tors.iter().find(|tor| tor.id == (i.saturating_sub(1)) as u64) {
-                    let pt2 = pt + arc.bend_plane_norm.normalize();
-                    z_dir = pt - pt2;
-                    y_dir = z_dir.cross(x_dir);
-                }
-                break;
-            }
-
-
-            else if let Some(arc) = tors.iter().find(|tor| tor.id == (i + 1) as u64) {
-                bend_radius=arc.bend_radius;
-                let min = fragment_len;
-
-                let arc_len = arc.angle().0 * arc.bend_radius;
-                fragment_len += arc_len;
-                let max = fragment_len;
-
-
-                if dist >= min && dist < max {
-                    id = arc.id;
-                    cp = Some(arc.bend_center_point);
-                    let offset = dist - min;
-
-                    theta = offset / arc.bend_radius;
-
-
-                    let v1: Vector3 = arc.ca.loc - arc.bend_center_point;
-                    let v2: Vector3 = arc.cb.loc - arc.bend_center_point;
-                    let axis = v1.cross(v2).normalize();
-
-                    let v_target = v1 * theta.cos() + axis.cross(v1) * theta.sin();
-
-                    pt = arc.bend_center_point + v_target;
-
-                    // Ориентация
-                    let pt2 = pt + arc.bend_plane_norm.normalize();
-                    let v3 = (pt - arc.bend_center_point).normalize();
-                    z_dir = pt - pt2;
-                    y_dir = v3;
-                    x_dir = y_dir.cross(z_dir);
-                    break;
-                }
-            }
-        }
-    }
-
-    (pt, x_dir, y_dir, z_dir, rot_deg, id, cp, len, theta,bend_radius)
-}
-
-
-
 pub fn delete_lra_row(row_index: i32, lraclr: &Vec<LRACLR>) -> Vec<LRACLR> {
     let mut v: Vec<LRACLR> = vec![];
     let mut counter = 0;
@@ -592,9 +660,13 @@ pub fn add_lra_row(row_index: i32, lraclr: &Vec<LRACLR>) -> Vec<LRACLR> {
 }
 
 
-pub fn save_csv(lraclr_arr: &Vec<LRACLR>, path: &PathBuf) {
+//With a cut length the table starts with a CUT;len line, parse_csv skips it as a header
+pub fn lraclr_to_csv(lraclr_arr: &Vec<LRACLR>, cut_length: Option<f64>) -> String {
     let mut s_out = String::new();
     if (!lraclr_arr.is_empty()) {
+        if let Some(cut) = cut_length {
+            s_out.push_str(format!("CUT;{:.3}\r\n", cut).as_str());
+        }
         for i in 0..lraclr_arr.len() - 1 {
             let lraclr = lraclr_arr[i].clone();
             s_out.push_str(format!("{}{}", i, ";").as_str());
@@ -608,15 +680,354 @@ pub fn save_csv(lraclr_arr: &Vec<LRACLR>, path: &PathBuf) {
         s_out.push_str(format!("{}{}", lraclr_arr.len() - 1, ";").as_str());
         s_out.push_str(format!("{}{}", last.l, ";").as_str());
         s_out.push_str("\r\n");
-        //let mut d="C:\\tmp\\".to_string();
-        //let mut d = "".to_string();
-        //d.push_str(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64().to_string().as_str());
-        //d.push_str(".csv");
+    }
+    s_out
+}
 
-        let f = OpenOptions::new().create(true).append(true).open(path).expect("Unable to open file");
-        let mut f = BufWriter::new(f);
-        f.write_all(s_out.as_bytes()).expect("Unable to write data");
+pub fn save_csv(lraclr_arr: &Vec<LRACLR>, path: &PathBuf) -> Result<(), ImportError> {
+    if (lraclr_arr.is_empty()) {
+        return Ok(());
     }
+    fs::write(path, lraclr_to_csv(lraclr_arr, None)).map_err(|e| ImportError::Io(e.to_string()))
 }
 
+//idx;L;R;A;CLR;0 rows as written by save_csv, the last row is idx;L
+pub fn parse_csv(text: &str, pipe_radius: f64) -> Result<Vec<LRACLR>, ImportError> {
+    let mut ret: Vec<LRACLR> = vec![];
+    let mut counter = 0;
+    for (line, values) in parse_number_rows(text)? {
+        if (values.len() < 2) {
+            return Err(ImportError::NotEnoughColumns { line: line, expected: 5, found: values.len() });
+        }
+        let value = |i: usize| -> f64 { if (i < values.len()) { values[i] } else { 0.0 } };
+        ret.push(LRACLR {
+            id1: counter,
+            id2: counter + 1,
+            l: value(1),
+            r: value(2),
+            a: value(3),
+            clr: value(4),
+            pipe_radius: pipe_radius,
+        });
+        counter = counter + 2;
+    }
+    if (ret.is_empty()) {
+        return Err(ImportError::NotEnoughPoints(0));
+    }
+    Ok(ret)
+}
+pub fn load_csv(path: &PathBuf, pipe_radius: f64) -> Result<Vec<LRACLR>, ImportError> {
+    let text = fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))?;
+    parse_csv(&text, pipe_radius)
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct XyzPoint {
+    pub id: usize,
+    pub sharp: [f64; 3],
+    pub tangent_in: Option<[f64; 3]>,
+    pub tangent_out: Option<[f64; 3]>,
+    pub clr: f64,
+}
+//Virtual sharps, tangent points and CLR in the part coordinate system
+pub fn lraclr_to_xyz(lraclr_arr: &Vec<LRACLR>, up_dir: &Vector3) -> Vec<XyzPoint> {
+    let mut ret: Vec<XyzPoint> = vec![];
+    let (cyls, tors) = cnc_to_poly(lraclr_arr, up_dir);
+    let to_arr = |p: Point3| -> [f64; 3] { [p.x, p.y, p.z] };
+    match cyls.first() {
+        None => return ret,
+        Some(c) => {
+            ret.push(XyzPoint { id: 0, sharp: to_arr(c.ca.loc), tangent_in: None, tangent_out: None, clr: 0.0 });
+        }
+    }
+    let mut tor_indx = 0;
+    lraclr_arr.iter().for_each(|lracl| {
+        if (lracl.clr > 0.0 && tor_indx < tors.len()) {
+            let t = &tors[tor_indx];
+            tor_indx = tor_indx + 1;
+            let tangent_len = lracl.clr * (Rad::from(Deg(abs(lracl.a))).0 / 2.0).tan();
+            let sharp = t.ca.loc + t.ca.dir.normalize() * tangent_len;
+            ret.push(XyzPoint {
+                id: ret.len(),
+                sharp: to_arr(sharp),
+                tangent_in: Some(to_arr(t.ca.loc)),
+                tangent_out: Some(to_arr(t.cb.loc)),
+                clr: lracl.clr,
+            });
+        }
+    });
+    let last = cyls.last().unwrap();
+    ret.push(XyzPoint { id: ret.len(), sharp: to_arr(last.cb.loc), tangent_in: None, tangent_out: None, clr: 0.0 });
+    ret
+}
+//X;Y;Z;CLR go first so the file can be imported back with load_xyz
+pub fn save_xyz_csv(points: &Vec<XyzPoint>, path: &PathBuf) -> std::io::Result<()> {
+    let mut s_out = String::new();
+    s_out.push_str("X;Y;Z;CLR;TIN_X;TIN_Y;TIN_Z;TOUT_X;TOUT_Y;TOUT_Z\r\n");
+    let fmt_pt = |p: &Option<[f64; 3]>| -> String {
+        match p {
+            None => ";;".to_string(),
+            Some(p) => format!("{:.4};{:.4};{:.4}", p[0], p[1], p[2]),
+        }
+    };
+    points.iter().for_each(|p| {
+        s_out.push_str(format!("{:.4};{:.4};{:.4};{:.4};", p.sharp[0], p.sharp[1], p.sharp[2], p.clr).as_str());
+        s_out.push_str(format!("{};{}\r\n", fmt_pt(&p.tangent_in), fmt_pt(&p.tangent_out)).as_str());
+    });
+    fs::write(path, s_out)
+}
+pub fn save_xyz_json(points: &Vec<XyzPoint>, path: &PathBuf) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(points).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    fs::write(path, json)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CycleOpKind {
+    Feed,
+    Rotate,
+    StackChange,
+    Clamp,
+    Bend,
+    Release,
+}
+impl Display for CycleOpKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CycleOpKind::Feed => write!(f, "Feed"),
+            CycleOpKind::Rotate => write!(f, "Rotate"),
+            CycleOpKind::StackChange => write!(f, "Stack change"),
+            CycleOpKind::Clamp => write!(f, "Clamp"),
+            CycleOpKind::Bend => write!(f, "Bend"),
+            CycleOpKind::Release => write!(f, "Release"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CycleOp {
+    pub row: usize,
+    pub kind: CycleOpKind,
+    pub time: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CycleTime {
+    pub ops: Vec<CycleOp>,
+    pub total: f64,
+}
+impl CycleTime {
+    pub fn time_of(&self, kind: CycleOpKind) -> f64 {
+        self.ops.iter().filter(|op| op.kind == kind).map(|op| op.time).sum()
+    }
+}
+
+//Point to point move with a trapezoidal velocity profile, triangular if the axis never reaches v_max
+pub fn move_time(dist: f64, axis: &AxisLimits) -> f64 {
+    let d = dist.abs();
+    if (d == 0.0 || axis.v_max <= 0.0) {
+        return 0.0;
+    }
+    if (axis.a_max <= 0.0) {
+        return d / axis.v_max;
+    }
+    let d_acc = axis.v_max * axis.v_max / axis.a_max;
+    if (d >= d_acc) {
+        d / axis.v_max + axis.v_max / axis.a_max
+    } else {
+        2.0 * (d / axis.a_max).sqrt()
+    }
+}
+
+//Time of every machine operation of the program, axes run one after another except clamp and pressure die
+pub fn cycle_time(lraclr_arr: &Vec<LRACLR>, machine: &MachineConfig, stacks: &Vec<DieStack>) -> CycleTime {
+    let axes = &machine.axes;
+    let changes = stack_changes(lraclr_arr, stacks);
+    let mut ops: Vec<CycleOp> = vec![];
+    lraclr_arr.iter().enumerate().for_each(|(row, lracl)| {
+        ops.push(CycleOp { row: row, kind: CycleOpKind::Feed, time: move_time(lracl.l, &axes.feed) });
+        if (lracl.clr > 0.0 && lracl.a.abs() > MIN_BEND_ANGLE_DEG) {
+            ops.push(CycleOp { row: row, kind: CycleOpKind::Rotate, time: move_time(lracl.r, &axes.rotation) });
+            if let Some(change) = changes.iter().find(|c| c.row == row) {
+                ops.push(CycleOp { row: row, kind: CycleOpKind::StackChange, time: move_time(change.dz, &axes.stack_change) });
+            }
+            let close = move_time(axes.clamp_stroke, &axes.clamp).max(move_time(axes.pressure_die_stroke, &axes.pressure_die));
+            ops.push(CycleOp { row: row, kind: CycleOpKind::Clamp, time: close });
+            ops.push(CycleOp { row: row, kind: CycleOpKind::Bend, time: move_time(lracl.a, &axes.bend) });
+            //Bend arm returns while the dies open
+            ops.push(CycleOp { row: row, kind: CycleOpKind::Release, time: close.max(move_time(lracl.a, &axes.bend)) });
+        }
+    });
+    let total = ops.iter().map(|op| op.time).sum();
+    CycleTime { ops: ops, total: total }
+}
+
+//Friction between the clamp die and the pipe
+pub const CLAMP_FRICTION: f64 = 0.3;
+//Torque over the plastic moment for friction in the dies and strain hardening past the flow stress
+pub const BEND_TORQUE_FACTOR: f64 = 1.2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadFlag {
+    Torque,
+    Clamp,
+    PressureDie,
+}
+
+//Moment in kNm, forces in kN
+#[derive(Debug, Clone, PartialEq)]
+pub struct BendLoad {
+    pub row: usize,
+    pub moment: f64,
+    pub clamp_force: f64,
+    pub pressure_die_force: f64,
+    pub flags: Vec<LoadFlag>,
+}
+impl BendLoad {
+    pub fn is_flagged(&self, flag: LoadFlag) -> bool {
+        self.flags.contains(&flag)
+    }
+}
+
+//Fully plastic moment of the tube section with the mean of yield and UTS as the flow stress.
+//The clamp holds the tangential pull M/CLR by friction, the pressure die reacts M over its length
+pub fn bend_loads(lraclr_arr: &Vec<LRACLR>, thickness: f64, material: &Material, machine: &MachineConfig, stacks: &Vec<DieStack>) -> Vec<BendLoad> {
+    let mut ret: Vec<BendLoad> = vec![];
+    lraclr_arr.iter().enumerate().for_each(|(row, lracl)| {
+        let d = lracl.pipe_radius * 2.0;
+        if (lracl.clr <= 0.0 || lracl.a.abs() < MIN_BEND_ANGLE_DEG || d <= 0.0 || thickness <= 0.0) {
+            return;
+        }
+        let inner_d = (d - thickness * 2.0).max(0.0);
+        let plastic_modulus = (d * d * d - inner_d * inner_d * inner_d) / 6.0;
+        let flow_stress = (material.yield_strength + material.uts) / 2.0;
+        let moment_nmm = BEND_TORQUE_FACTOR * flow_stress * plastic_modulus;
+        let pressure_die_len = match stacks.iter().find(|s| s.fits(lracl.clr, d)) {
+            Some(s) => s.pressure_die.length,
+            None => DieStack::generic(lracl.clr, d).pressure_die.length,
+        };
+        let moment = moment_nmm / 1.0e6;
+        let clamp_force = moment_nmm / lracl.clr / CLAMP_FRICTION / 1000.0;
+        let pressure_die_force = moment_nmm / pressure_die_len / 1000.0;
+
+        let capacity = &machine.capacity;
+        let mut flags: Vec<LoadFlag> = vec![];
+        if (moment > capacity.bend_torque) { flags.push(LoadFlag::Torque); }
+        if (clamp_force > capacity.clamp_force) { flags.push(LoadFlag::Clamp); }
+        if (pressure_die_force > capacity.pressure_die_force) { flags.push(LoadFlag::PressureDie); }
+        ret.push(BendLoad {
+            row: row,
+            moment: moment,
+            clamp_force: clamp_force,
+            pressure_die_force: pressure_die_force,
+            flags: flags,
+        });
+    });
+    ret
+}
+
+//Row i of a test program on a 20 mm pipe, shared by the tests of the algo modules
+#[cfg(test)]
+pub(crate) fn test_row(i: i32, l: f64, r: f64, a: f64, clr: f64) -> LRACLR {
+    LRACLR { id1: i * 2, id2: i * 2 + 1, l: l, r: r, a: a, clr: clr, pipe_radius: 10.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xyz_round_trip() {
+        //The first rotation is lost in XYZ, the table starts with r = 0
+        let program = vec![
+            test_row(0, 100.0, 0.0, 90.0, 60.0),
+            test_row(1, 50.0, 45.0, 30.0, 80.0),
+            test_row(2, 120.0, -90.0, 120.0, 60.0),
+            test_row(3, 80.0, 170.0, 15.0, 100.0),
+            test_row(4, 200.0, 0.0, 0.0, 0.0),
+        ];
+        let pts: Vec<(Point3, f64)> = lraclr_to_xyz(&program, &Vector3::new(0.0, 0.0, 1.0)).iter()
+            .map(|p| (Point3::new(p.sharp[0], p.sharp[1], p.sharp[2]), p.clr))
+            .collect();
+        assert_eq!(pts.len(), program.len() + 1);
+        let back = xyz_to_lraclr(&pts, 10.0).unwrap();
+        assert_eq!(back.len(), program.len());
+        program.iter().zip(back.iter()).for_each(|(a, b)| {
+            assert!((a.l - b.l).abs() < 1e-6, "l {} {}", a.l, b.l);
+            assert!((a.r - b.r).abs() < 1e-6, "r {} {}", a.r, b.r);
+            assert!((a.a - b.a).abs() < 1e-6, "a {} {}", a.a, b.a);
+            assert!((a.clr - b.clr).abs() < 1e-6, "clr {} {}", a.clr, b.clr);
+        });
+    }
+
+    #[test]
+    fn sharps_of_a_quarter_bend() {
+        //Tangent length of a 90 degree bend is the CLR, both legs end 50 mm past the tangent points
+        let program = vec![test_row(0, 100.0, 0.0, 90.0, 50.0), test_row(1, 100.0, 0.0, 0.0, 0.0)];
+        let pts = lraclr_to_xyz(&program, &Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(pts.len(), 3);
+        let dist = |a: &[f64; 3], b: &[f64; 3]| ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt();
+        assert!((dist(&pts[0].sharp, &pts[1].sharp) - 150.0).abs() < 1e-6);
+        assert!((dist(&pts[1].sharp, &pts[2].sharp) - 150.0).abs() < 1e-6);
+        assert!((dist(&pts[0].sharp, &pts[2].sharp) - 150.0 * 2.0_f64.sqrt()).abs() < 1e-6);
+        assert!((dist(&pts[1].sharp, &pts[1].tangent_in.unwrap()) - 50.0).abs() < 1e-6);
+        assert!((dist(&pts[1].sharp, &pts[1].tangent_out.unwrap()) - 50.0).abs() < 1e-6);
+        assert_eq!(pts[1].clr, 50.0);
+        assert!(pts[0].tangent_in.is_none() && pts[2].tangent_out.is_none());
+    }
+
+    #[test]
+    fn cycle_time_of_one_bend() {
+        //Default axes: 1000 mm feed 2.5 s, 90 deg rotation 1 s, dies close in 0.4 s, 90 deg bend 2 s
+        let program = vec![test_row(0, 1000.0, 90.0, 90.0, 40.0), test_row(1, 1000.0, 0.0, 0.0, 0.0)];
+        let cycle = cycle_time(&program, &MachineConfig::default(), &vec![DieStack::generic(40.0, 20.0)]);
+        let kinds: Vec<CycleOpKind> = cycle.ops.iter().map(|op| op.kind).collect();
+        assert_eq!(kinds, vec![CycleOpKind::Feed, CycleOpKind::Rotate, CycleOpKind::Clamp, CycleOpKind::Bend, CycleOpKind::Release, CycleOpKind::Feed]);
+        assert!((cycle.time_of(CycleOpKind::Feed) - 5.0).abs() < 1e-9);
+        assert!((cycle.time_of(CycleOpKind::Rotate) - 1.0).abs() < 1e-9);
+        assert!((cycle.time_of(CycleOpKind::Clamp) - 0.4).abs() < 1e-9);
+        assert!((cycle.time_of(CycleOpKind::Release) - 2.0).abs() < 1e-9);
+        assert!((cycle.total - 10.4).abs() < 1e-9);
+        //Short move never reaches v_max
+        assert!((move_time(100.0, &MachineConfig::default().axes.feed) - 2.0 * 0.1_f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn loads_of_a_20x2_tube() {
+        //Plastic modulus (20^3 - 16^3) / 6, flow stress 250 MPa
+        let material = Material::new("Test", "Steel", 200.0, 300.0, 25.0, 210.0, 7850.0);
+        let program = vec![test_row(0, 100.0, 0.0, 90.0, 40.0), test_row(1, 100.0, 0.0, 0.0, 0.0)];
+        let mut machine = MachineConfig::default();
+        let loads = bend_loads(&program, 2.0, &material, &machine, &vec![]);
+        assert_eq!(loads.len(), 1);
+        let moment_nmm = 1.2 * 250.0 * (8000.0 - 4096.0) / 6.0;
+        assert!((loads[0].moment - 0.1952).abs() < 1e-9);
+        assert!((loads[0].clamp_force - moment_nmm / 40.0 / 0.3 / 1000.0).abs() < 1e-9);
+        assert!((loads[0].pressure_die_force - moment_nmm / 300.0 / 1000.0).abs() < 1e-9);
+        assert!(loads[0].flags.is_empty());
+
+        machine.capacity.bend_torque = 0.1;
+        let loads = bend_loads(&program, 2.0, &material, &machine, &vec![]);
+        assert_eq!(loads[0].flags, vec![LoadFlag::Torque]);
+    }
+
+    #[test]
+    fn parse_csv_decimal_comma_and_header() {
+        let text = "CUT;512,5\r\nidx;L;R;A;CLR;0\r\n0;100,5;0;90;60,25;0\r\n1;50;-45,5;30;80;0\r\n2;200,75;\r\n";
+        let program = parse_csv(text, 10.0).unwrap();
+        assert_eq!(program.len(), 3);
+        assert_eq!(program[0].l, 100.5);
+        assert_eq!(program[0].clr, 60.25);
+        assert_eq!(program[1].r, -45.5);
+        assert_eq!(program[1].a, 30.0);
+        assert_eq!(program[2].l, 200.75);
+        assert_eq!(program[2].clr, 0.0);
+        assert_eq!((program[2].id1, program[2].id2), (4, 5));
+        assert_eq!(program[2].pipe_radius, 10.0);
+    }
+
+    #[test]
+    fn parse_csv_rejects_text_after_data() {
+        let text = "0;100;0;90;60;0\nidx;L;R;A;CLR;0\n";
+        assert!(matches!(parse_csv(text, 10.0), Err(ImportError::BadNumber { line: 2, .. })));
+    }
+}
@@ -0,0 +1,478 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use glam::{Mat4, Vec3};
+use is_odd::IsOdd;
+use truck_base::cgmath64::Vector3;
+use crate::algo::cnc::{CycleOpKind, ImportError, LRACLR};
+use crate::algo::kinematics::{machine_root_rotation, AxisRegisters, MachineNode, MACHINE_ROOT_OFFSET, MACHINE_SCALE};
+use crate::algo::machine::MachineConfig;
+use crate::algo::path::PipePath;
+use crate::algo::solids::roller_triangles;
+use crate::algo::tooling::{stack_changes, stack_levels, DieStack};
+
+//Machine models relative to the asset folder
+pub const MACHINE_ASSETS: &str = "machines/m2";
+//Height of the top stack when the head is in the start position, m
+pub const STACK_TOP_Z: f32 = 0.015;
+//Die solids are modelled around the origin and moved under the bend arm, m
+pub const DIE_OFFSET: Vec3 = Vec3::new(-0.199, 0.0, 0.0);
+pub const CLAMP_DIE_OFFSET: Vec3 = Vec3::new(-0.131, 0.0, 0.0);
+pub const PRESSURE_DIE_OFFSET: Vec3 = Vec3::new(-0.188, -0.304, 0.0);
+//Cells along the longest side of a part
+const GRID_CELLS: f32 = 48.0;
+
+//Die mesh in the machine frame, stacks are mounted top down
+pub fn die_placement(offset: Vec3, level: f64) -> Mat4 {
+    let z = STACK_TOP_Z - (level / 1000.0) as f32;
+    Mat4::from_translation(offset + Vec3::new(0.0, 0.0, z))
+        * Mat4::from_scale(Vec3::splat(1.0 / MACHINE_SCALE))
+        * Mat4::from_quat(machine_root_rotation())
+        * Mat4::from_translation(-MACHINE_ROOT_OFFSET)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachinePart {
+    SASI,
+    MALAFA,
+    PENS,
+    PALKA2M,
+    PALKAM,
+    DayamaAlt,
+    PALKA3,
+    DayamamKizakArka,
+    DayamamKizak,
+    MengeneAlt,
+    MENGENE,
+    BendDie,
+    DieInsert,
+    ClampDie,
+    PressureDie,
+}
+impl MachinePart {
+    pub const MODELS: [MachinePart; 11] = [
+        MachinePart::SASI,
+        MachinePart::MALAFA,
+        MachinePart::PENS,
+        MachinePart::PALKA2M,
+        MachinePart::PALKAM,
+        MachinePart::DayamaAlt,
+        MachinePart::PALKA3,
+        MachinePart::DayamamKizakArka,
+        MachinePart::DayamamKizak,
+        MachinePart::MengeneAlt,
+        MachinePart::MENGENE,
+    ];
+    //Model file of the part, dies are generated from the tooling
+    pub fn file(&self) -> Option<&'static str> {
+        match self {
+            MachinePart::SASI => Some("sasi.glb"),
+            MachinePart::MALAFA => Some("malafa.glb"),
+            MachinePart::PENS => Some("pens.glb"),
+            MachinePart::PALKA2M => Some("palka2m.glb"),
+            MachinePart::PALKAM => Some("palkam.glb"),
+            MachinePart::DayamaAlt => Some("dayama_alt2.glb"),
+            MachinePart::PALKA3 => Some("palka3.glb"),
+            MachinePart::DayamamKizakArka => Some("dayamam_kizak_arka3.glb"),
+            MachinePart::DayamamKizak => Some("dayamam_kizak3.glb"),
+            MachinePart::MengeneAlt => Some("mengene_alt2.glb"),
+            MachinePart::MENGENE => Some("mengene2.glb"),
+            _ => None,
+        }
+    }
+    //Node of the kinematic chain the part is mounted on
+    pub fn node(&self) -> MachineNode {
+        match self {
+            MachinePart::SASI | MachinePart::MALAFA => MachineNode::Root,
+            MachinePart::PENS => MachineNode::Pusher,
+            MachinePart::PALKA2M => MachineNode::ZBlock,
+            MachinePart::PALKAM | MachinePart::DayamaAlt => MachineNode::YBlock,
+            MachinePart::PALKA3 => MachineNode::Palka3,
+            MachinePart::DayamamKizakArka => MachineNode::PressureSlide,
+            MachinePart::DayamamKizak | MachinePart::PressureDie => MachineNode::PressureDie,
+            MachinePart::MengeneAlt | MachinePart::BendDie | MachinePart::DieInsert => MachineNode::BendArm,
+            MachinePart::MENGENE | MachinePart::ClampDie => MachineNode::ClampSlide,
+        }
+    }
+}
+impl Display for MachinePart {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MachinePart::BendDie => write!(f, "bend die"),
+            MachinePart::DieInsert => write!(f, "die insert"),
+            MachinePart::ClampDie => write!(f, "clamp die"),
+            MachinePart::PressureDie => write!(f, "pressure die"),
+            part => write!(f, "{:?}", part),
+        }
+    }
+}
+
+//Uniform grid over the triangles of one part in its own frame
+#[derive(Debug, Clone)]
+struct TriGrid {
+    tris: Vec<[Vec3; 3]>,
+    min: Vec3,
+    max: Vec3,
+    cell: f32,
+    dims: [usize; 3],
+    cells: Vec<Vec<u32>>,
+}
+impl TriGrid {
+    fn new(tris: Vec<[Vec3; 3]>) -> Self {
+        let tris: Vec<[Vec3; 3]> = tris.into_iter().filter(|t| (t[1] - t[0]).cross(t[2] - t[0]).length_squared() > 0.0).collect();
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        tris.iter().flatten().for_each(|p| {
+            min = min.min(*p);
+            max = max.max(*p);
+        });
+        if (tris.is_empty()) {
+            min = Vec3::ZERO;
+            max = Vec3::ZERO;
+        }
+        let cell = ((max - min).max_element() / GRID_CELLS).max(f32::EPSILON);
+        let dims = [
+            (((max.x - min.x) / cell) as usize + 1),
+            (((max.y - min.y) / cell) as usize + 1),
+            (((max.z - min.z) / cell) as usize + 1),
+        ];
+        let mut grid = Self { tris: vec![], min: min, max: max, cell: cell, dims: dims, cells: vec![vec![]; dims[0] * dims[1] * dims[2]] };
+        tris.iter().enumerate().for_each(|(i, t)| {
+            let (lo, hi) = grid.range(t[0].min(t[1]).min(t[2]), t[0].max(t[1]).max(t[2]));
+            for x in lo[0]..=hi[0] {
+                for y in lo[1]..=hi[1] {
+                    for z in lo[2]..=hi[2] {
+                        let idx = grid.index(x, y, z);
+                        grid.cells[idx].push(i as u32);
+                    }
+                }
+            }
+        });
+        grid.tris = tris;
+        grid
+    }
+    fn index(&self, x: usize, y: usize, z: usize) -> usize {
+        (z * self.dims[1] + y) * self.dims[0] + x
+    }
+    fn range(&self, lo: Vec3, hi: Vec3) -> ([usize; 3], [usize; 3]) {
+        let to_cell = |p: Vec3, axis: usize| {
+            (((p[axis] - self.min[axis]) / self.cell).max(0.0) as usize).min(self.dims[axis] - 1)
+        };
+        ([to_cell(lo, 0), to_cell(lo, 1), to_cell(lo, 2)], [to_cell(hi, 0), to_cell(hi, 1), to_cell(hi, 2)])
+    }
+    //Closest triangle distance if it is below r
+    fn hit(&self, p: Vec3, r: f32) -> Option<f32> {
+        if (self.tris.is_empty() || p.cmplt(self.min - r).any() || p.cmpgt(self.max + r).any()) {
+            return None;
+        }
+        let (lo, hi) = self.range(p - r, p + r);
+        let mut found: Option<f32> = None;
+        for x in lo[0]..=hi[0] {
+            for y in lo[1]..=hi[1] {
+                for z in lo[2]..=hi[2] {
+                    self.cells[self.index(x, y, z)].iter().for_each(|i| {
+                        let t = &self.tris[*i as usize];
+                        let d = p.distance(closest_on_triangle(p, t[0], t[1], t[2]));
+                        if (d < r && found.map_or(true, |f| d < f)) {
+                            found = Some(d);
+                        }
+                    });
+                }
+            }
+        }
+        found
+    }
+}
+
+//Closest point on a triangle, Ericson "Real-Time Collision Detection" 5.1.5
+fn closest_on_triangle(p: Vec3, a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if (d1 <= 0.0 && d2 <= 0.0) {
+        return a;
+    }
+    let bp = p - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if (d3 >= 0.0 && d4 <= d3) {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if (vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0) {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if (d6 >= 0.0 && d5 <= d6) {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if (vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0) {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if (va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0) {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+#[derive(Debug, Clone)]
+struct PartGeometry {
+    part: MachinePart,
+    stack: Option<usize>,
+    grid: TriGrid,
+}
+
+//Triangles of the machine parts in the frames of their moving groups
+#[derive(Debug, Clone)]
+pub struct MachineModel {
+    parts: Vec<PartGeometry>,
+    stacks: Vec<DieStack>,
+}
+impl MachineModel {
+    //Reads the part models from the asset folder and builds the dies of every stack
+    pub fn load(assets: &Path, stacks: &Vec<DieStack>, pipe_d: f64) -> Result<MachineModel, ImportError> {
+        let mut parts: Vec<PartGeometry> = vec![];
+        for part in MachinePart::MODELS {
+            if let Some(file) = part.file() {
+                let tris = load_glb_triangles(&assets.join(MACHINE_ASSETS).join(file))?;
+                parts.push(PartGeometry { part: part, stack: None, grid: TriGrid::new(tris) });
+            }
+        }
+        let stacks = if stacks.is_empty() { vec![DieStack::generic(pipe_d * 1.5, pipe_d)] } else { stacks.clone() };
+        let levels = stack_levels(&stacks);
+        stacks.iter().enumerate().for_each(|(i, stack)| {
+            let [die, insert, clamp, support] = roller_triangles(stack, pipe_d);
+            let dies = [
+                (MachinePart::BendDie, die, DIE_OFFSET),
+                (MachinePart::DieInsert, insert, DIE_OFFSET),
+                (MachinePart::ClampDie, clamp, CLAMP_DIE_OFFSET),
+                (MachinePart::PressureDie, support, PRESSURE_DIE_OFFSET),
+            ];
+            dies.into_iter().for_each(|(part, tris, offset)| {
+                let m = die_placement(offset, levels[i]);
+                let tris = tris.iter().map(|t| t.map(|p| m.transform_point3(Vec3::new(p.x as f32, p.y as f32, p.z as f32)))).collect();
+                parts.push(PartGeometry { part: part, stack: Some(i), grid: TriGrid::new(tris) });
+            });
+        });
+        Ok(MachineModel { parts: parts, stacks: stacks })
+    }
+}
+
+fn load_glb_triangles(path: &Path) -> Result<Vec<[Vec3; 3]>, ImportError> {
+    let gltf = gltf::Gltf::open(path).map_err(|e| ImportError::Io(format!("{:?}: {}", path, e)))?;
+    let buffers = gltf::import_buffers(&gltf.document, path.parent(), gltf.blob.clone()).map_err(|e| ImportError::Format(format!("{:?}: {}", path, e)))?;
+    let mut tris: Vec<[Vec3; 3]> = vec![];
+    if let Some(scene) = gltf.document.scenes().next() {
+        scene.nodes().for_each(|node| collect_node(&node, Mat4::IDENTITY, &buffers, &mut tris));
+    }
+    Ok(tris)
+}
+fn collect_node(node: &gltf::Node, parent: Mat4, buffers: &Vec<gltf::buffer::Data>, tris: &mut Vec<[Vec3; 3]>) {
+    let m = parent * Mat4::from_cols_array_2d(&node.transform().matrix());
+    if let Some(mesh) = node.mesh() {
+        mesh.primitives().filter(|p| p.mode() == gltf::mesh::Mode::Triangles).for_each(|primitive| {
+            let reader = primitive.reader(|b| Some(&buffers[b.index()]));
+            if let Some(positions) = reader.read_positions() {
+                let positions: Vec<Vec3> = positions.map(|p| m.transform_point3(Vec3::from(p))).collect();
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..positions.len() as u32).collect(),
+                };
+                indices.chunks_exact(3).for_each(|c| {
+                    tris.push([positions[c[0] as usize], positions[c[1] as usize], positions[c[2] as usize]]);
+                });
+            }
+        });
+    }
+    node.children().for_each(|child| collect_node(&child, m, buffers, tris));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollisionSetup {
+    //Pipe travel between two checked positions, mm
+    pub step: f64,
+    //Rotation between two checked positions, degrees
+    pub rot_step: f64,
+    //Allowed overlap, covers the pipe sitting in the die grooves and the die tessellation
+    pub tolerance: f64,
+}
+impl Default for CollisionSetup {
+    fn default() -> Self {
+        Self {
+            step: 5.0,
+            rot_step: 5.0,
+            tolerance: 1.0,
+        }
+    }
+}
+
+//First contact of the program, point and part bounds in the machine frame at that moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collision {
+    pub row: usize,
+    pub kind: CycleOpKind,
+    pub part: MachinePart,
+    pub stack: Option<usize>,
+    //Simulator position and pipe rotation of the contact
+    pub t: f64,
+    pub rot_step: f64,
+    pub point: Vec3,
+    pub depth: f64,
+    pub bounds: (Vec3, Vec3),
+}
+impl Display for Collision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let part = match self.stack {
+            Some(s) => format!("{} of stack {}", self.part, s + 1),
+            None => self.part.to_string(),
+        };
+        write!(f, "Row {}: {} hits {} by {:.1} mm", self.row, self.kind, part, self.depth)
+    }
+}
+
+struct Pose {
+    row: usize,
+    kind: CycleOpKind,
+    dist: f64,
+    rot_step: f64,
+    registers: AxisRegisters,
+}
+
+//Runs the machine program on the machine model and returns the first pipe to machine contact
+pub fn find_collision(cmnd: &Vec<LRACLR>, up_dir: &Vector3, model: &MachineModel, machine: &MachineConfig, setup: &CollisionSetup) -> Option<Collision> {
+    let path = PipePath::new(cmnd, up_dir);
+    let len = path.len();
+    if (cmnd.is_empty() || len <= 0.0 || setup.step <= 0.0) {
+        return None;
+    }
+    let r = cmnd[0].pipe_radius;
+    let spacing = (r / 2.0).clamp(1.0, setup.step);
+    let centre: Vec<(f64, Vec3)> = (0..=(len / spacing) as usize).map(|k| {
+        let s = (k as f64 * spacing).min(len - 1e-6);
+        let pt = path.at_dist(s).pt;
+        (s, Vec3::new(pt.x as f32, pt.y as f32, pt.z as f32))
+    }).collect();
+
+    let loaded = AxisRegisters::loaded(machine, len, r * 2.0);
+    let levels = stack_levels(&model.stacks);
+    let changes = stack_changes(cmnd, &model.stacks);
+    let head_z = |row: usize, bending: bool| {
+        let stack = changes.iter().filter(|c| c.row < row || (bending && c.row == row)).last().map_or(0, |c| c.to);
+        loaded.mov_z + (levels[stack] - levels[0]) / 1000.0
+    };
+
+    let mut poses: Vec<Pose> = vec![];
+    path.spans().iter().for_each(|(id, start, end)| {
+        let row = (*id / 2) as usize;
+        if (end <= start) {
+            return;
+        }
+        let count = ((end - start) / setup.step).ceil() as usize;
+        let dists: Vec<f64> = (0..=count).map(|k| (start + k as f64 * setup.step).min(end - 1e-6)).collect();
+        if (id.is_odd()) {
+            let rot = LRACLR::rotate_by_id((*id - 1) as i32, cmnd);
+            let registers = AxisRegisters { mov_z: head_z(row, false), ..loaded };
+            let turns = (rot.abs() / setup.rot_step.max(0.1)).ceil() as usize;
+            for j in 0..=turns {
+                let rot_step = if (turns == 0) { 0.0 } else { -rot + rot * j as f64 / turns as f64 };
+                poses.push(Pose { row: row, kind: CycleOpKind::Rotate, dist: *start, rot_step: rot_step, registers: AxisRegisters { dx: *start, ..registers } });
+            }
+            dists.iter().for_each(|dist| {
+                let registers = AxisRegisters { dx: *dist, bend_angle: path.at_dist(*dist).theta, mov_z: head_z(row, true), ..loaded }.clamped();
+                poses.push(Pose { row: row, kind: CycleOpKind::Bend, dist: *dist, rot_step: 0.0, registers: registers });
+            });
+        } else {
+            dists.iter().for_each(|dist| {
+                let rot_step = -path.at_dist(*dist).rot_deg;
+                let registers = AxisRegisters { dx: *dist, mov_z: head_z(row, false), ..loaded };
+                poses.push(Pose { row: row, kind: CycleOpKind::Feed, dist: *dist, rot_step: rot_step, registers: registers });
+            });
+        }
+    });
+
+    let radius = (r - setup.tolerance).max(0.0) as f32;
+    for pose in poses.iter() {
+        let m = path.at_dist(pose.dist).machine_matrix(pose.rot_step);
+        //Formed part follows the frame of the current point, the rest of the pipe lies straight along -X
+        let mut points: Vec<Vec3> = centre.iter().filter(|(s, _)| *s <= pose.dist).map(|(_, p)| m.transform_point3(*p)).collect();
+        let rest = len - pose.dist;
+        (0..=(rest / spacing) as usize).for_each(|k| points.push(Vec3::new(-((k as f64 * spacing).min(rest)) as f32, 0.0, 0.0)));
+
+        for geometry in model.parts.iter() {
+            let pm = pose.registers.node_matrix(geometry.part.node());
+            let scale = pm.x_axis.truncate().length();
+            let inv = pm.inverse();
+            let local_r = radius / scale;
+            let hit = points.iter().find_map(|p| geometry.grid.hit(inv.transform_point3(*p), local_r).map(|d| (*p, d)));
+            if let Some((point, d)) = hit {
+                let corners: Vec<Vec3> = (0..8).map(|i| {
+                    let c = Vec3::new(
+                        if (i & 1 == 0) { geometry.grid.min.x } else { geometry.grid.max.x },
+                        if (i & 2 == 0) { geometry.grid.min.y } else { geometry.grid.max.y },
+                        if (i & 4 == 0) { geometry.grid.min.z } else { geometry.grid.max.z },
+                    );
+                    pm.transform_point3(c)
+                }).collect();
+                let lo = corners.iter().fold(Vec3::splat(f32::MAX), |a, c| a.min(*c));
+                let hi = corners.iter().fold(Vec3::splat(f32::MIN), |a, c| a.max(*c));
+                return Some(Collision {
+                    row: pose.row,
+                    kind: pose.kind,
+                    part: geometry.part,
+                    stack: geometry.stack,
+                    t: pose.dist / len,
+                    rot_step: pose.rot_step,
+                    point: point,
+                    depth: r - (d * scale) as f64,
+                    bounds: (lo, hi),
+                });
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::cnc::test_row;
+    use crate::algo::kinematics::machine_root;
+
+    //Square plate across the X axis at x, given in mm and moved into the frame of the machine root
+    fn plate_model(x: f32, z: f32) -> MachineModel {
+        let inv = machine_root().inverse();
+        let corners = [Vec3::new(x, -50.0, z - 50.0), Vec3::new(x, 50.0, z - 50.0), Vec3::new(x, 50.0, z + 50.0), Vec3::new(x, -50.0, z + 50.0)];
+        let [a, b, c, d] = corners.map(|p| inv.transform_point3(p));
+        let part = PartGeometry { part: MachinePart::SASI, stack: None, grid: TriGrid::new(vec![[a, b, c], [a, c, d]]) };
+        MachineModel { parts: vec![part], stacks: vec![DieStack::generic(30.0, 20.0)] }
+    }
+
+    #[test]
+    fn closest_point_inside_and_outside() {
+        let (a, b, c) = (Vec3::ZERO, Vec3::X, Vec3::Y);
+        assert_eq!(closest_on_triangle(Vec3::new(0.25, 0.25, 1.0), a, b, c), Vec3::new(0.25, 0.25, 0.0));
+        assert_eq!(closest_on_triangle(Vec3::new(2.0, -1.0, 0.0), a, b, c), b);
+    }
+
+    #[test]
+    fn pipe_through_plate() {
+        let collision = find_collision(&vec![test_row(0, 300.0, 0.0, 0.0, 0.0)], &Vector3::new(0.0, 0.0, 1.0), &plate_model(-100.0, 0.0), &MachineConfig::default(), &CollisionSetup::default());
+        let collision = collision.unwrap();
+        assert_eq!(collision.row, 0);
+        assert_eq!(collision.kind, CycleOpKind::Feed);
+        assert_eq!(collision.part, MachinePart::SASI);
+        assert!(collision.depth > 9.0);
+    }
+
+    #[test]
+    fn plate_above_the_pipe() {
+        let collision = find_collision(&vec![test_row(0, 300.0, 0.0, 0.0, 0.0)], &Vector3::new(0.0, 0.0, 1.0), &plate_model(-100.0, 200.0), &MachineConfig::default(), &CollisionSetup::default());
+        assert!(collision.is_none());
+    }
+}
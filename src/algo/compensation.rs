@@ -0,0 +1,273 @@
+use std::fs;
+use std::path::PathBuf;
+use cgmath::num_traits::signum;
+use serde::{Deserialize, Serialize};
+use crate::algo::cnc::{ImportError, LRACLR};
+use crate::algo::TOLE;
+
+//Overbend in degrees needed to get the target angle after the pipe springs back
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpringbackPoint {
+    pub angle: f64,
+    pub overbend: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpringbackModel {
+    pub material: String,
+    //Die CLR the model was measured on, None fits every die
+    pub die_clr: Option<f64>,
+    pub points: Vec<SpringbackPoint>,
+    //Relative growth of the part CLR against the die CLR, for reference only: the program keeps the die CLR
+    pub clr_growth: f64,
+}
+impl SpringbackModel {
+    pub fn new(material: &str, points: &[(f64, f64)], clr_growth: f64) -> Self {
+        Self {
+            material: material.to_string(),
+            die_clr: None,
+            points: points.iter().map(|(a, o)| SpringbackPoint { angle: *a, overbend: *o }).collect(),
+            clr_growth: clr_growth,
+        }
+    }
+    pub fn overbend(&self, target_angle: f64) -> f64 {
+        let a = target_angle.abs();
+        let mut pts = self.points.clone();
+        pts.sort_by(|p1, p2| p1.angle.partial_cmp(&p2.angle).unwrap());
+        match (pts.first(), pts.last()) {
+            (Some(first), Some(last)) => {
+                if (a <= first.angle) {
+                    if (first.angle > 0.0) { first.overbend * a / first.angle } else { first.overbend }
+                } else if (a >= last.angle) {
+                    last.overbend
+                } else {
+                    let mut ret = 0.0;
+                    pts.windows(2).for_each(|w| {
+                        if (a >= w[0].angle && a <= w[1].angle) {
+                            let k = (a - w[0].angle) / (w[1].angle - w[0].angle);
+                            ret = w[0].overbend + (w[1].overbend - w[0].overbend) * k;
+                        }
+                    });
+                    ret
+                }
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpringbackTable {
+    pub models: Vec<SpringbackModel>,
+}
+impl Default for SpringbackTable {
+    fn default() -> Self {
+        Self {
+            models: vec![
+                SpringbackModel::new("Steel", &[(0.0, 0.0), (30.0, 1.2), (90.0, 2.5), (180.0, 4.0)], 0.02),
+                SpringbackModel::new("Stainless", &[(0.0, 0.0), (30.0, 2.0), (90.0, 4.0), (180.0, 6.5)], 0.03),
+                SpringbackModel::new("Aluminium", &[(0.0, 0.0), (30.0, 0.8), (90.0, 1.8), (180.0, 3.0)], 0.015),
+                SpringbackModel::new("Copper", &[(0.0, 0.0), (30.0, 0.5), (90.0, 1.0), (180.0, 1.8)], 0.01),
+                SpringbackModel::new("Titanium", &[(0.0, 0.0), (30.0, 3.0), (90.0, 6.0), (180.0, 9.5)], 0.04),
+            ],
+        }
+    }
+}
+impl SpringbackTable {
+    //Die specific model first, then the generic one of the material
+    pub fn find(&self, material: &str, clr: f64) -> Option<&SpringbackModel> {
+        let same_material = || self.models.iter().filter(|m| m.material.eq_ignore_ascii_case(material));
+        match same_material().find(|m| m.die_clr.map_or(false, |d| (d - clr).abs() < TOLE)) {
+            Some(m) => Some(m),
+            None => same_material().find(|m| m.die_clr.is_none()),
+        }
+    }
+}
+
+//Neutral axis position from the bend inside as a part of D, 0.5 is the centerline
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct KFactorPoint {
+    pub clr_d: f64,
+    pub k_factor: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElongationModel {
+    pub material: String,
+    pub points: Vec<KFactorPoint>,
+}
+impl ElongationModel {
+    pub fn new(material: &str, points: &[(f64, f64)]) -> Self {
+        Self {
+            material: material.to_string(),
+            points: points.iter().map(|(r, k)| KFactorPoint { clr_d: *r, k_factor: *k }).collect(),
+        }
+    }
+    pub fn k_factor(&self, clr_d: f64) -> f64 {
+        let mut pts = self.points.clone();
+        pts.sort_by(|p1, p2| p1.clr_d.partial_cmp(&p2.clr_d).unwrap());
+        match (pts.first(), pts.last()) {
+            (Some(first), Some(last)) => {
+                if (clr_d <= first.clr_d) {
+                    first.k_factor
+                } else if (clr_d >= last.clr_d) {
+                    last.k_factor
+                } else {
+                    let mut ret = 0.5;
+                    pts.windows(2).for_each(|w| {
+                        if (clr_d >= w[0].clr_d && clr_d <= w[1].clr_d) {
+                            let k = (clr_d - w[0].clr_d) / (w[1].clr_d - w[0].clr_d);
+                            ret = w[0].k_factor + (w[1].k_factor - w[0].k_factor) * k;
+                        }
+                    });
+                    ret
+                }
+            }
+            _ => 0.5,
+        }
+    }
+    //Centerline arc minus the material really consumed by the bend
+    pub fn elongation(&self, lracl: &LRACLR) -> f64 {
+        let d = lracl.pipe_radius * 2.0;
+        if (lracl.clr <= 0.0 || d <= 0.0) {
+            return 0.0;
+        }
+        let k = self.k_factor(lracl.clr / d);
+        let shift = (0.5 - k) * d;
+        lracl.lt() * shift / lracl.clr
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ElongationTable {
+    pub models: Vec<ElongationModel>,
+}
+impl Default for ElongationTable {
+    fn default() -> Self {
+        Self {
+            models: vec![
+                ElongationModel::new("Steel", &[(1.0, 0.30), (1.5, 0.35), (2.0, 0.40), (3.0, 0.45), (5.0, 0.5)]),
+                ElongationModel::new("Stainless", &[(1.0, 0.28), (1.5, 0.33), (2.0, 0.38), (3.0, 0.44), (5.0, 0.5)]),
+                ElongationModel::new("Aluminium", &[(1.0, 0.33), (1.5, 0.38), (2.0, 0.42), (3.0, 0.46), (5.0, 0.5)]),
+                ElongationModel::new("Copper", &[(1.0, 0.35), (1.5, 0.40), (2.0, 0.44), (3.0, 0.47), (5.0, 0.5)]),
+                ElongationModel::new("Titanium", &[(1.0, 0.27), (1.5, 0.32), (2.0, 0.37), (3.0, 0.43), (5.0, 0.5)]),
+            ],
+        }
+    }
+}
+impl ElongationTable {
+    pub fn find(&self, material: &str) -> Option<&ElongationModel> {
+        self.models.iter().find(|m| m.material.eq_ignore_ascii_case(material))
+    }
+    pub fn bend_elongation(&self, lracl: &LRACLR, material: &str) -> f64 {
+        match self.find(material) {
+            None => 0.0,
+            Some(m) => m.elongation(lracl),
+        }
+    }
+}
+
+pub fn load_elongation(path: &PathBuf) -> Result<ElongationTable, ImportError> {
+    let text = fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))?;
+    serde_json::from_str(&text).map_err(|e| ImportError::Format(e.to_string()))
+}
+
+//Each bend stretches by its elongation, half of it is taken from the straights on both sides
+pub fn compensate_feeds(nominal: &Vec<LRACLR>, table: &ElongationTable, material: &str) -> Vec<LRACLR> {
+    let mut ret: Vec<LRACLR> = nominal.clone();
+    let mut prev_half = 0.0;
+    ret.iter_mut().for_each(|lracl| {
+        let half = table.bend_elongation(lracl, material) / 2.0;
+        lracl.l = (lracl.l - prev_half - half).max(0.0);
+        prev_half = half;
+    });
+    ret
+}
+
+//Developed length of the finished part
+pub fn cut_length(nominal: &Vec<LRACLR>, table: &ElongationTable, material: &str) -> f64 {
+    let mut ret = 0.0;
+    nominal.iter().for_each(|lracl| {
+        ret = ret + lracl.l + lracl.lt() - table.bend_elongation(lracl, material);
+    });
+    ret
+}
+
+//Extra stock in mm, end_trim is cut off each end after bending
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CutAllowances {
+    pub front_grip: f64,
+    pub rear_grip: f64,
+    pub end_trim: f64,
+}
+impl Default for CutAllowances {
+    fn default() -> Self {
+        Self {
+            front_grip: 0.0,
+            rear_grip: 0.0,
+            end_trim: 0.0,
+        }
+    }
+}
+
+//Length to cut from the bar: developed length plus grip and trim allowances
+pub fn stock_length(nominal: &Vec<LRACLR>, table: &ElongationTable, material: &str, allowances: &CutAllowances) -> f64 {
+    if (nominal.is_empty()) {
+        return 0.0;
+    }
+    cut_length(nominal, table, material) + allowances.front_grip + allowances.rear_grip + allowances.end_trim * 2.0
+}
+
+pub fn load_springback(path: &PathBuf) -> Result<SpringbackTable, ImportError> {
+    let text = fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))?;
+    serde_json::from_str(&text).map_err(|e| ImportError::Format(e.to_string()))
+}
+
+//Commanded feeds and angles for the nominal part geometry, CLR stays the die CLR of the nominal row
+pub fn machine_program(nominal: &Vec<LRACLR>, table: &SpringbackTable, elongation: &ElongationTable, material: &str) -> Vec<LRACLR> {
+    let mut ret: Vec<LRACLR> = compensate_feeds(nominal, elongation, material);
+    ret.iter_mut().for_each(|lracl| {
+        if (lracl.clr > 0.0) {
+            match table.find(material, lracl.clr) {
+                None => {}
+                Some(m) => {
+                    lracl.a = lracl.a + signum(lracl.a) * m.overbend(lracl.a);
+                }
+            }
+        }
+    });
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use crate::algo::cnc::test_row;
+    use crate::algo::material::MaterialCatalog;
+
+    #[test]
+    fn catalog_families_have_tables() {
+        let springback = SpringbackTable::default();
+        let elongation = ElongationTable::default();
+        MaterialCatalog::default().materials.iter().for_each(|m| {
+            assert!(springback.find(&m.family, 0.0).is_some(), "springback {}", m.family);
+            assert!(elongation.find(&m.family).is_some(), "elongation {}", m.family);
+        });
+    }
+
+    #[test]
+    fn elongation_of_a_quarter_bend() {
+        //CLR/D 2 sits between the points, K 0.4 moves the neutral axis 2 mm to the inside of a 20 mm pipe
+        let table = ElongationTable { models: vec![ElongationModel::new("Test", &[(1.0, 0.3), (3.0, 0.5)])] };
+        let program = vec![test_row(0, 100.0, 0.0, 90.0, 40.0), test_row(1, 100.0, 0.0, 0.0, 0.0)];
+        assert!((table.find("Test").unwrap().k_factor(2.0) - 0.4).abs() < 1e-12);
+        assert!((table.bend_elongation(&program[0], "Test") - PI).abs() < 1e-9);
+
+        let feeds = compensate_feeds(&program, &table, "Test");
+        assert!((feeds[0].l - (100.0 - PI / 2.0)).abs() < 1e-9);
+        assert!((feeds[1].l - (100.0 - PI / 2.0)).abs() < 1e-9);
+        assert!((cut_length(&program, &table, "Test") - (200.0 + 19.0 * PI)).abs() < 1e-9);
+        assert_eq!(table.bend_elongation(&program[0], "Unknown"), 0.0);
+    }
+}
@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use crate::algo::cnc::{LRACLR, MIN_BEND_ANGLE_DEG};
+use crate::algo::material::Material;
+
+//Customer limits in %, ovality_factor is the empirical k of k*D^2/(CLR*t) for bending without a mandrel
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DeformationLimits {
+    pub max_thinning: f64,
+    pub max_thickening: f64,
+    pub max_ovality: f64,
+    pub ovality_factor: f64,
+}
+impl Default for DeformationLimits {
+    fn default() -> Self {
+        Self {
+            max_thinning: 20.0,
+            max_thickening: 25.0,
+            max_ovality: 8.0,
+            ovality_factor: 0.8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeformationFlag {
+    Thinning,
+    Thickening,
+    Ovality,
+    //Outer fiber strain over the elongation at break of the material
+    Strain,
+}
+
+//Walls in mm, the rest in %
+#[derive(Debug, Clone, PartialEq)]
+pub struct BendDeformation {
+    pub row: usize,
+    pub outer_wall: f64,
+    pub inner_wall: f64,
+    pub thinning: f64,
+    pub thickening: f64,
+    pub ovality: f64,
+    pub outer_strain: f64,
+    pub flags: Vec<DeformationFlag>,
+}
+impl BendDeformation {
+    pub fn is_flagged(&self, flag: DeformationFlag) -> bool {
+        self.flags.contains(&flag)
+    }
+}
+
+//Walls from the neutral axis on the centerline: t_out = t*2R/(2R+D), t_in = t*2R/(2R-D)
+pub fn bend_deformation(lracl: &LRACLR, row: usize, thickness: f64, material: Option<&Material>, limits: &DeformationLimits) -> Option<BendDeformation> {
+    let d = lracl.pipe_radius * 2.0;
+    if (lracl.clr <= 0.0 || lracl.a.abs() < MIN_BEND_ANGLE_DEG || d <= 0.0 || thickness <= 0.0) {
+        return None;
+    }
+    let two_r = lracl.clr * 2.0;
+    let outer_wall = thickness * two_r / (two_r + d);
+    let inner_wall = if (two_r > d) { thickness * two_r / (two_r - d) } else { thickness * 2.0 };
+    let thinning = (1.0 - outer_wall / thickness) * 100.0;
+    let thickening = (inner_wall / thickness - 1.0) * 100.0;
+    let ovality = limits.ovality_factor * d * d / (lracl.clr * thickness);
+    let outer_strain = d / two_r * 100.0;
+
+    let mut flags: Vec<DeformationFlag> = vec![];
+    if (thinning > limits.max_thinning) { flags.push(DeformationFlag::Thinning); }
+    if (thickening > limits.max_thickening) { flags.push(DeformationFlag::Thickening); }
+    if (ovality > limits.max_ovality) { flags.push(DeformationFlag::Ovality); }
+    if let Some(m) = material {
+        if (outer_strain > m.elongation) { flags.push(DeformationFlag::Strain); }
+    }
+    Some(BendDeformation {
+        row: row,
+        outer_wall: outer_wall,
+        inner_wall: inner_wall,
+        thinning: thinning,
+        thickening: thickening,
+        ovality: ovality,
+        outer_strain: outer_strain,
+        flags: flags,
+    })
+}
+
+pub fn program_deformation(lraclr_arr: &Vec<LRACLR>, thickness: f64, material: Option<&Material>, limits: &DeformationLimits) -> Vec<BendDeformation> {
+    lraclr_arr.iter().enumerate()
+        .filter_map(|(row, lracl)| bend_deformation(lracl, row, thickness, material, limits))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::cnc::test_row;
+
+    #[test]
+    fn tight_bend_of_a_20x2_tube() {
+        //2R 60 against D 20: walls 2*60/80 and 2*60/40, ovality 0.8*20^2/(30*2), outer fiber 20/60
+        let material = Material::new("Test", "Steel", 200.0, 300.0, 25.0, 210.0, 7850.0);
+        let program = vec![test_row(0, 100.0, 0.0, 90.0, 30.0), test_row(1, 100.0, 0.0, 0.0, 0.0)];
+        let found = program_deformation(&program, 2.0, Some(&material), &DeformationLimits::default());
+        assert_eq!(found.len(), 1);
+        let b = &found[0];
+        assert_eq!(b.row, 0);
+        assert!((b.outer_wall - 1.5).abs() < 1e-12);
+        assert!((b.inner_wall - 3.0).abs() < 1e-12);
+        assert!((b.thinning - 25.0).abs() < 1e-9);
+        assert!((b.thickening - 50.0).abs() < 1e-9);
+        assert!((b.ovality - 16.0 / 3.0).abs() < 1e-9);
+        assert!((b.outer_strain - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(b.flags, vec![DeformationFlag::Thinning, DeformationFlag::Thickening, DeformationFlag::Strain]);
+    }
+}
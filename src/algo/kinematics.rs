@@ -0,0 +1,159 @@
+use std::f32::consts::PI;
+use glam::{Mat4, Quat, Vec3};
+#[cfg(feature = "bevy")]
+use bevy::prelude::Component;
+use crate::algo::machine::{HeadLayout, MachineConfig};
+
+//Placement of the machine models, world units are mm and the bend die tangent point is the origin
+pub const MACHINE_ROOT_OFFSET: Vec3 = Vec3::new(-3058.0, -289.37, -154.89);
+pub const MACHINE_SCALE: f32 = 1000.0;
+//Bend arm axis in the machine frame, m
+pub const HEAD_PIVOT: Vec3 = Vec3::new(0.0903719, 3.058, 0.0);
+pub const PALKA3_OFFSET: Vec3 = Vec3::new(0.0903719, 3.058, -0.3658858);
+//Y block position that brings the bend die groove to the axis of a zero diameter pipe, m
+pub const Y_BLOCK_START: f64 = 0.199;
+//Pressure die and clamp die slides when the dies are closed, m
+pub const PRESSURE_SLIDE_START: f64 = -0.011;
+pub const CLAMP_SLIDE_START: f64 = -0.068;
+//Open dies are moved away by these multiples of the pipe diameter
+pub const BEND_DIE_OPEN: f64 = 2.0;
+pub const SIDE_DIES_OPEN: f64 = 3.0;
+
+pub fn machine_root_rotation() -> Quat {
+    Quat::from_rotation_y(PI) * Quat::from_rotation_z(PI / 2.0)
+}
+pub fn machine_root() -> Mat4 {
+    Mat4::from_scale_rotation_translation(Vec3::splat(MACHINE_SCALE), machine_root_rotation(), MACHINE_ROOT_OFFSET)
+}
+
+//Moving nodes of the machine, the simulator hierarchy and the collision model share this chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bevy", derive(Component))]
+pub enum MachineNode {
+    Root,
+    Pusher,
+    ZBlock,
+    YBlock,
+    Palka3,
+    PressureSlide,
+    PressureDie,
+    BendPivot,
+    BendArm,
+    ClampSlide,
+}
+impl MachineNode {
+    pub fn parent(&self) -> Option<MachineNode> {
+        match self {
+            MachineNode::Root => None,
+            MachineNode::Pusher | MachineNode::ZBlock => Some(MachineNode::Root),
+            MachineNode::YBlock => Some(MachineNode::ZBlock),
+            MachineNode::Palka3 | MachineNode::PressureSlide | MachineNode::BendPivot => Some(MachineNode::YBlock),
+            MachineNode::PressureDie => Some(MachineNode::PressureSlide),
+            MachineNode::BendArm => Some(MachineNode::BendPivot),
+            MachineNode::ClampSlide => Some(MachineNode::BendArm),
+        }
+    }
+}
+
+//Axis positions of the machine, dx in mm, moves in m, bend angle in radians
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisRegisters {
+    pub dx: f64,
+    pub bend_angle: f64,
+    pub mov_z: f64,
+    pub mov_y: f64,
+    pub mov_y_clamp: f64,
+    pub mov_static_y: f64,
+    pub mov_static_y_clamp: f64,
+    pub mov_static_x: f64,
+    pub mov_rotated_y: f64,
+    pub mov_rotated_y_clamp: f64,
+    pub mov_pusher_x: f64,
+}
+impl Default for AxisRegisters {
+    fn default() -> Self {
+        Self {
+            dx: 0.0,
+            bend_angle: 0.0,
+            mov_z: HeadLayout::default().start_z / 1000.0,
+            mov_y: 0.0,
+            mov_y_clamp: 0.0,
+            mov_static_y: PRESSURE_SLIDE_START,
+            mov_static_y_clamp: 0.0,
+            mov_static_x: 0.0,
+            mov_rotated_y: CLAMP_SLIDE_START,
+            mov_rotated_y_clamp: 0.0,
+            mov_pusher_x: 0.0,
+        }
+    }
+}
+impl AxisRegisters {
+    //Start position after the pipe is loaded, head on the top stack and dies open
+    pub fn loaded(machine: &MachineConfig, pipe_len: f64, pipe_d: f64) -> Self {
+        Self {
+            mov_z: machine.head.start_z / 1000.0,
+            mov_y: Y_BLOCK_START - pipe_d / 1000.0,
+            mov_pusher_x: (machine.carriage_stroke - pipe_len) / 1000.0,
+            ..Self::default()
+        }.opened(pipe_d)
+    }
+    pub fn opened(&self, pipe_d: f64) -> Self {
+        let clamp = pipe_d / 1000.0;
+        Self {
+            mov_y_clamp: -clamp * BEND_DIE_OPEN,
+            mov_static_y_clamp: clamp * SIDE_DIES_OPEN,
+            mov_rotated_y_clamp: clamp * SIDE_DIES_OPEN,
+            ..*self
+        }
+    }
+    pub fn clamped(&self) -> Self {
+        Self { mov_y_clamp: 0.0, mov_static_y_clamp: 0.0, mov_rotated_y_clamp: 0.0, ..*self }
+    }
+    //Transform of the node relative to its parent
+    pub fn local_matrix(&self, node: MachineNode) -> Mat4 {
+        match node {
+            MachineNode::Root => machine_root(),
+            MachineNode::Pusher => Mat4::from_translation(Vec3::new(0.0, (self.dx / 1000.0 + self.mov_pusher_x) as f32, 0.0)),
+            MachineNode::ZBlock => Mat4::from_translation(Vec3::new(0.0, 0.0, self.mov_z as f32)),
+            MachineNode::YBlock => Mat4::from_translation(Vec3::new((self.mov_y + self.mov_y_clamp) as f32, 0.0, 0.0)),
+            MachineNode::Palka3 => Mat4::from_translation(PALKA3_OFFSET),
+            MachineNode::PressureSlide => Mat4::from_translation(Vec3::new((self.mov_static_y + self.mov_static_y_clamp) as f32, 0.0, 0.0)),
+            MachineNode::PressureDie => Mat4::from_translation(Vec3::new(0.0, self.mov_static_x as f32, 0.0)),
+            MachineNode::BendPivot => Mat4::from_translation(HEAD_PIVOT) * Mat4::from_rotation_z(self.bend_angle as f32),
+            MachineNode::BendArm => Mat4::from_translation(-HEAD_PIVOT),
+            MachineNode::ClampSlide => Mat4::from_translation(Vec3::new((self.mov_rotated_y + self.mov_rotated_y_clamp) as f32, 0.0, 0.0)),
+        }
+    }
+    //Transform of the node in the world frame
+    pub fn node_matrix(&self, node: MachineNode) -> Mat4 {
+        match node.parent() {
+            Some(parent) => self.node_matrix(parent) * self.local_matrix(node),
+            None => self.local_matrix(node),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loaded_start_position() {
+        let machine = MachineConfig::default();
+        let regs = AxisRegisters::loaded(&machine, 1000.0, 20.0);
+        assert!((regs.mov_pusher_x - 2.521).abs() < 1e-9);
+        assert!((regs.mov_y - 0.179).abs() < 1e-9);
+        assert!((regs.mov_z - 0.01).abs() < 1e-9);
+        assert!((regs.mov_y_clamp + 0.04).abs() < 1e-9);
+        assert_eq!(regs.clamped().mov_static_y_clamp, 0.0);
+    }
+
+    #[test]
+    fn bend_arm_turns_around_the_pivot() {
+        let regs = AxisRegisters { bend_angle: 1.0, ..AxisRegisters::default() };
+        let y_block = regs.node_matrix(MachineNode::YBlock);
+        let pivot = y_block.transform_point3(HEAD_PIVOT);
+        let arm = regs.node_matrix(MachineNode::BendArm);
+        assert!((arm.transform_point3(HEAD_PIVOT) - pivot).length() < 1e-3);
+    }
+}
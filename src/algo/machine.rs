@@ -0,0 +1,119 @@
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::algo::cnc::ImportError;
+
+pub const MACHINE_FILE: &str = "machine.json";
+
+//Max velocity and acceleration of one axis, mm or degrees per second
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AxisLimits {
+    pub v_max: f64,
+    pub a_max: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MachineAxes {
+    pub feed: AxisLimits,
+    pub rotation: AxisLimits,
+    pub bend: AxisLimits,
+    pub clamp: AxisLimits,
+    pub pressure_die: AxisLimits,
+    pub stack_change: AxisLimits,
+    //Open to closed travel of the clamp and pressure dies
+    pub clamp_stroke: f64,
+    pub pressure_die_stroke: f64,
+}
+impl Default for MachineAxes {
+    fn default() -> Self {
+        Self {
+            feed: AxisLimits { v_max: 500.0, a_max: 1000.0 },
+            rotation: AxisLimits { v_max: 180.0, a_max: 360.0 },
+            bend: AxisLimits { v_max: 60.0, a_max: 120.0 },
+            clamp: AxisLimits { v_max: 100.0, a_max: 500.0 },
+            pressure_die: AxisLimits { v_max: 100.0, a_max: 500.0 },
+            stack_change: AxisLimits { v_max: 50.0, a_max: 200.0 },
+            clamp_stroke: 20.0,
+            pressure_die_stroke: 20.0,
+        }
+    }
+}
+
+//Rated loads, torque in kNm, forces in kN
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MachineCapacity {
+    pub bend_torque: f64,
+    pub clamp_force: f64,
+    pub pressure_die_force: f64,
+}
+impl Default for MachineCapacity {
+    fn default() -> Self {
+        Self {
+            bend_torque: 5.0,
+            clamp_force: 150.0,
+            pressure_die_force: 60.0,
+        }
+    }
+}
+
+//Bend head, stacks lists tooling stack ids top down and may carry the same CLR on several levels.
+//An empty list mounts one stack per CLR in order of first use
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeadLayout {
+    #[serde(default)]
+    pub stacks: Vec<String>,
+    pub max_levels: usize,
+    //Head height with the top stack in line with the pipe
+    pub start_z: f64,
+}
+impl Default for HeadLayout {
+    fn default() -> Self {
+        Self {
+            stacks: vec![],
+            max_levels: 3,
+            start_z: 10.0,
+        }
+    }
+}
+
+//Limits of the bender, lengths in mm, angles in degrees
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MachineConfig {
+    pub name: String,
+    pub max_bend_angle: f64,
+    pub carriage_stroke: f64,
+    pub rotation_min: f64,
+    pub rotation_max: f64,
+    //Straight needed by the collet at the tail of the pipe
+    pub collet_grip: f64,
+    #[serde(default)]
+    pub axes: MachineAxes,
+    #[serde(default)]
+    pub capacity: MachineCapacity,
+    #[serde(default)]
+    pub head: HeadLayout,
+}
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            name: "M2".to_string(),
+            max_bend_angle: 190.0,
+            carriage_stroke: 3521.0,
+            rotation_min: -360.0,
+            rotation_max: 360.0,
+            collet_grip: 50.0,
+            axes: MachineAxes::default(),
+            capacity: MachineCapacity::default(),
+            head: HeadLayout::default(),
+        }
+    }
+}
+
+pub fn load_machine(path: &PathBuf) -> Result<MachineConfig, ImportError> {
+    let text = fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))?;
+    serde_json::from_str(&text).map_err(|e| ImportError::Format(e.to_string()))
+}
+pub fn save_machine(machine: &MachineConfig, path: &PathBuf) -> Result<(), ImportError> {
+    let json = serde_json::to_string_pretty(machine).map_err(|e| ImportError::Format(e.to_string()))?;
+    fs::write(path, json).map_err(|e| ImportError::Io(e.to_string()))
+}
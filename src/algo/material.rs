@@ -0,0 +1,83 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::algo::cnc::ImportError;
+
+pub const MATERIAL_FILE: &str = "materials.json";
+
+//Strength in MPa, elongation at break in %, E in GPa, density in kg/m3
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    pub name: String,
+    //Key of the springback and elongation tables, Steel, Stainless, Aluminium, Copper, Titanium
+    pub family: String,
+    pub yield_strength: f64,
+    pub uts: f64,
+    pub elongation: f64,
+    pub e_modulus: f64,
+    pub density: f64,
+}
+impl Material {
+    pub fn new(name: &str, family: &str, yield_strength: f64, uts: f64, elongation: f64, e_modulus: f64, density: f64) -> Self {
+        Self {
+            name: name.to_string(),
+            family: family.to_string(),
+            yield_strength: yield_strength,
+            uts: uts,
+            elongation: elongation,
+            e_modulus: e_modulus,
+            density: density,
+        }
+    }
+    //Weight in kg of a pipe, all sizes in mm
+    pub fn pipe_weight(&self, d: f64, thickness: f64, len: f64) -> f64 {
+        let inner_d = (d - thickness * 2.0).max(0.0);
+        let area = PI / 4.0 * (d * d - inner_d * inner_d);
+        area * len * 1e-9 * self.density
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaterialCatalog {
+    pub materials: Vec<Material>,
+}
+impl Default for MaterialCatalog {
+    fn default() -> Self {
+        Self {
+            materials: vec![
+                Material::new("S235", "Steel", 235.0, 360.0, 26.0, 210.0, 7850.0),
+                Material::new("S355", "Steel", 355.0, 470.0, 22.0, 210.0, 7850.0),
+                Material::new("E235", "Steel", 235.0, 340.0, 25.0, 210.0, 7850.0),
+                Material::new("AISI 304", "Stainless", 210.0, 520.0, 45.0, 193.0, 7900.0),
+                Material::new("AISI 316L", "Stainless", 170.0, 485.0, 40.0, 193.0, 8000.0),
+                Material::new("AW-6061 T6", "Aluminium", 240.0, 260.0, 8.0, 69.0, 2700.0),
+                Material::new("AW-6063 T6", "Aluminium", 170.0, 215.0, 8.0, 69.0, 2700.0),
+                Material::new("AW-5083 H111", "Aluminium", 125.0, 275.0, 15.0, 71.0, 2660.0),
+                Material::new("Cu-DHP R250", "Copper", 200.0, 250.0, 20.0, 120.0, 8940.0),
+                Material::new("Ti Gr2", "Titanium", 275.0, 345.0, 20.0, 105.0, 4510.0),
+            ],
+        }
+    }
+}
+impl MaterialCatalog {
+    pub fn find(&self, name: &str) -> Option<&Material> {
+        self.materials.iter().find(|m| m.name.eq_ignore_ascii_case(name))
+    }
+    //Springback and elongation key, names that are not in the catalog are used as is
+    pub fn family(&self, name: &str) -> String {
+        match self.find(name) {
+            Some(m) => m.family.clone(),
+            None => name.to_string(),
+        }
+    }
+}
+
+pub fn load_materials(path: &PathBuf) -> Result<MaterialCatalog, ImportError> {
+    let text = fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))?;
+    serde_json::from_str(&text).map_err(|e| ImportError::Format(e.to_string()))
+}
+pub fn save_materials(catalog: &MaterialCatalog, path: &PathBuf) -> Result<(), ImportError> {
+    let json = serde_json::to_string_pretty(catalog).map_err(|e| ImportError::Format(e.to_string()))?;
+    fs::write(path, json).map_err(|e| ImportError::Io(e.to_string()))
+}
@@ -1,6 +1,19 @@
 pub mod cnc;
+#[cfg(feature = "bevy")]
 pub mod triangulation;
 pub mod solids;
+pub mod project;
+pub mod compensation;
+pub mod post;
+pub mod tooling;
+pub mod machine;
+pub mod validation;
+pub mod material;
+pub mod deformation;
+pub mod nesting;
+pub mod path;
+pub mod kinematics;
+pub mod collision;
 
 use crate::algo::cnc::{gen_cyl, AnimState, LRACLR};
 use cgmath::num_traits::real::Real;
@@ -21,10 +34,13 @@ use std::io::{BufWriter, Read, Write};
 use std::ops::{Mul, Sub};
 use std::sync::atomic::Ordering;
 use std::vec::IntoIter;
+use glam::Vec3;
+#[cfg(feature = "bevy")]
 use bevy::asset::RenderAssetUsages;
-use bevy::math::Vec3;
+#[cfg(feature = "bevy")]
 use bevy::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
-use bevy::prelude::{Component, Extrusion, Mesh, Quat, RegularPolygon};
+#[cfg(feature = "bevy")]
+use bevy::prelude::{Extrusion, Mesh, Quat, RegularPolygon};
 
 use cgmath::num_traits::abs;
 use cgmath::num_traits::float::FloatCore;
@@ -85,6 +101,59 @@ pub const EXTRA_R_CALC: f64 = 1.2;
 pub const MAX_BEND_RADIUS: f64 = 1000.0;
 pub const DIVIDER: f64 = 100000000.0;
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum StepAnalysisError {
+    Decode(String),
+    Parse(String),
+    NoDataSection,
+    MissingEntity { entity: &'static str, id: u64 },
+    InvalidGeometry { entity: &'static str, id: u64, reason: String },
+    NoPipeBody,
+    AmbiguousRadiusGroup(Vec<f64>),
+    PipeEndsNotFound(usize),
+    BrokenChain,
+    EmptyProgram,
+}
+impl std::fmt::Display for StepAnalysisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepAnalysisError::Decode(e) => write!(f, "Unable to decode STEP file: {}", e),
+            StepAnalysisError::Parse(e) => write!(f, "Unable to parse STEP file: {}", e),
+            StepAnalysisError::NoDataSection => write!(f, "STEP file has no DATA section"),
+            StepAnalysisError::MissingEntity { entity, id } => write!(f, "Entity #{} references a missing or unsupported {}", id, entity),
+            StepAnalysisError::InvalidGeometry { entity, id, reason } => write!(f, "Invalid {} #{}: {}", entity, id, reason),
+            StepAnalysisError::NoPipeBody => write!(f, "No pipe body (cylinders joined by bends) found"),
+            StepAnalysisError::AmbiguousRadiusGroup(radiuses) => write!(f, "Several pipe bodies with the same number of segments found, radiuses {:?}, the first one is loaded", radiuses),
+            StepAnalysisError::PipeEndsNotFound(qty) => write!(f, "Pipe must have 2 free ends, found {}", qty),
+            StepAnalysisError::BrokenChain => write!(f, "Pipe segments do not form a continuous chain"),
+            StepAnalysisError::EmptyProgram => write!(f, "No bending operations found"),
+        }
+    }
+}
+impl std::error::Error for StepAnalysisError {}
+
+#[derive(Clone, Debug)]
+pub struct PipeBody {
+    pub segments: Vec<LRACLR>,
+    pub outer_radius: f64,
+    pub inner_radius: Option<f64>,
+}
+impl PipeBody {
+    pub fn wall_thickness(&self) -> Option<f64> {
+        match self.inner_radius {
+            None => None,
+            Some(inner_r) => Some(self.outer_radius - inner_r),
+        }
+    }
+    pub fn total_len(&self) -> f64 {
+        let (tot_l, _out_d) = LRACLR::total_len_out_d(&self.segments);
+        tot_l
+    }
+    pub fn bend_count(&self) -> usize {
+        self.segments.iter().filter(|s| s.clr > 0.0).count()
+    }
+}
+
 #[derive(Clone,Debug)]
 pub struct MainCircle {
     pub id: u64,
@@ -569,6 +638,7 @@ impl MainCylinder {
     pub fn recalculate_h(&mut self) {
         self.h = self.cb.loc.distance(self.ca.loc);
     }
+    #[cfg(feature = "bevy")]
     pub fn to_mesh(&self) -> Mesh{
         let rp: RegularPolygon = RegularPolygon::new(self.r as f32, CILINDER_TRIANGULATION_SEGMENTS);
         let (p1, p2) = {
@@ -597,6 +667,7 @@ impl MainCylinder {
         real_mesh
     }
 
+    #[cfg(feature = "bevy")]
     pub fn to_mesh_with_seg_len(&self, seg_len:f64) -> Vec<Mesh>{
         let mut seg_len:f64=seg_len;
         let rp: RegularPolygon = RegularPolygon::new(self.r as f32, CILINDER_TRIANGULATION_SEGMENTS);
@@ -943,6 +1014,7 @@ impl BendToro {
 
         dxf_lines
     }
+    #[cfg(feature = "bevy")]
     pub fn to_mesh(&self, prev_dir: &Vector3, segments: usize, tube_segments: usize) -> Mesh {
         let mut index: u32 = 0;
         let mut vertices: Vec<Vec3> = Vec::new();
@@ -1153,6 +1225,7 @@ impl BendToro {
         mesh
     }
 
+    #[cfg(feature = "bevy")]
     pub fn to_mesh_with_seg_len(&self, prev_dir: &Vector3, segments: usize, tube_segments: usize, segment_len:f64) -> Mesh {
         let mut index: u32 = 0;
         let mut vertices: Vec<Vec3> = Vec::new();
@@ -1409,15 +1482,16 @@ pub fn extract_vertex(t: &Table, vertex: &PlaceHolder<VertexPointHolder>, scale:
         PlaceHolder::Owned(_) => None,
     }
 }
-pub fn extract_plane_points(table: &Table, scale: f64) -> Vec<Point3> {
+pub fn extract_plane_points(table: &Table, scale: f64) -> Result<Vec<Point3>, StepAnalysisError> {
     let mut points: Vec<Point3> = vec![];
+    let mut first_err: Option<StepAnalysisError> = None;
     table.shell.iter().for_each(|(k, v)| {
         v.cfs_faces.iter().for_each(|face_holder| {
             match face_holder {
                 PlaceHolder::Ref(name) => {
                     let id = name_to_id(name.clone());
                     match table.face_surface.get(&id) {
-                        None => { println!("NOT FOUND") }
+                        None => { warn!("skipped {}", StepAnalysisError::MissingEntity { entity: "FACE_SURFACE", id: id }) }
                         Some(face_holder) => {
                             let face_bounds: &Vec<PlaceHolder<FaceBoundHolder>> = &face_holder.bounds;
                             match &face_holder.face_geometry {
@@ -1448,10 +1522,15 @@ pub fn extract_plane_points(table: &Table, scale: f64) -> Vec<Point3> {
                                                                                                             match table.edge_curve.get(&name_to_id(name.clone())) {
                                                                                                                 None => {}
                                                                                                                 Some(c) => {
-                                                                                                                    let sp = extract_vertex(&table, &c.edge_start, scale).unwrap();
-                                                                                                                    let ep = extract_vertex(&table, &c.edge_end, scale).unwrap();
-                                                                                                                    points.push(sp);
-                                                                                                                    points.push(ep);
+                                                                                                                    match (extract_vertex(&table, &c.edge_start, scale), extract_vertex(&table, &c.edge_end, scale)) {
+                                                                                                                        (Some(sp), Some(ep)) => {
+                                                                                                                            points.push(sp);
+                                                                                                                            points.push(ep);
+                                                                                                                        }
+                                                                                                                        _ => {
+                                                                                                                            first_err.get_or_insert(StepAnalysisError::MissingEntity { entity: "VERTEX_POINT", id: name_to_id(name.clone()) });
+                                                                                                                        }
+                                                                                                                    }
                                                                                                                 }
                                                                                                             }
                                                                                                         }
@@ -1486,10 +1565,23 @@ pub fn extract_plane_points(table: &Table, scale: f64) -> Vec<Point3> {
             }
         })
     });
-    points
+    match first_err {
+        None => Ok(points),
+        Some(e) => Err(e),
+    }
 }
 
-pub fn analyze_stp_path(f:File) -> Vec<LRACLR> {
+pub fn analyze_stp_path(f:File) -> Result<Vec<LRACLR>, StepAnalysisError> {
+    analyze_stp_path_body(f).map(|body| body.segments)
+}
+pub fn analyze_stp_path_body(f:File) -> Result<PipeBody, StepAnalysisError> {
+    let (body, warning) = select_main_body(&analyze_stp_path_bodies(f)?)?;
+    if let Some(w) = warning {
+        warn!("{}", w);
+    }
+    Ok(body)
+}
+pub fn analyze_stp_path_bodies(f:File) -> Result<Vec<PipeBody>, StepAnalysisError> {
     let mut reader = Reader::new(&f);
     let mut stp = String::new();
     reader.into_iter().for_each(|c|{
@@ -1501,79 +1593,83 @@ pub fn analyze_stp_path(f:File) -> Vec<LRACLR> {
             }
         }
     });
-
-    let scale = extact_scale(&stp);
-
-    let fixed_stp: String =stp.replace(",(),", ",'',");
-
-    let exchange = ruststep::parser::parse(&fixed_stp).unwrap();
-    let table: Table = Table::from_data_section(&exchange.data[0]);
-
-    let (cyls, tors) = extract_cyls(&table, scale);
-
-    let cyls_no_dubs = MainCylinder::remove_dublicates(&cyls);
-    let cyls_merged = MainCylinder::merge(&cyls_no_dubs);
-    let bend_toros_no_dublicates: Vec<BendToro> = BendToro::remove_dublicates(&tors);
-    let merged_tors = BendToro::merge(&bend_toros_no_dublicates);
-    let racalculated_tors: Vec<BendToro> = recalc_tors_tole(&cyls_merged, &merged_tors);
-    let lracmd: Vec<LRACLR> = find_bending_surface(&cyls_merged, &racalculated_tors,&table,scale);
-
-    lracmd
-
+    analyze_stp_str(&stp)
 }
 
-pub fn analyze_stp(_stp: &Vec<u8>) -> Vec<LRACLR> {
+pub fn analyze_stp(_stp: &Vec<u8>) -> Result<Vec<LRACLR>, StepAnalysisError> {
+    analyze_stp_body(_stp).map(|body| body.segments)
+}
+pub fn analyze_stp_body(_stp: &Vec<u8>) -> Result<PipeBody, StepAnalysisError> {
+    let (body, warning) = select_main_body(&analyze_stp_bodies(_stp)?)?;
+    if let Some(w) = warning {
+        warn!("{}", w);
+    }
+    Ok(body)
+}
+pub fn analyze_stp_bodies(_stp: &Vec<u8>) -> Result<Vec<PipeBody>, StepAnalysisError> {
     let mut transcoded = DecodeReaderBytesBuilder::new().encoding(Some(WINDOWS_1251)).build(_stp.as_slice());
     let mut buf: Vec<u8> = vec![];
-    let mut stp: String = String::new();
-    match transcoded.read_to_end(&mut buf) {
-        Ok(b) => match String::from_utf8(buf) {
-            Ok(cont) => {
-                stp = cont.clone();
-            }
-            Err(e) => {
-                println!("{:?}", e)
-            }
+    let stp: String = match transcoded.read_to_end(&mut buf) {
+        Ok(_) => match String::from_utf8(buf) {
+            Ok(cont) => cont,
+            Err(e) => return Err(StepAnalysisError::Decode(e.to_string())),
         },
-        Err(e) => {
-            println!("{:?}", e)
-        }
-    }
+        Err(e) => return Err(StepAnalysisError::Decode(e.to_string())),
+    };
+    analyze_stp_str(&stp)
+}
+
+fn analyze_stp_str(stp: &String) -> Result<Vec<PipeBody>, StepAnalysisError> {
     let scale = extact_scale(&stp);
 
     let fixed_stp: String =stp.replace(",(),", ",'',");
 
-    let exchange = ruststep::parser::parse(&fixed_stp).unwrap();
-    let table: Table = Table::from_data_section(&exchange.data[0]);
+    let exchange = match ruststep::parser::parse(&fixed_stp) {
+        Ok(exchange) => exchange,
+        Err(e) => return Err(StepAnalysisError::Parse(format!("{:?}", e))),
+    };
+    let table: Table = match exchange.data.first() {
+        None => return Err(StepAnalysisError::NoDataSection),
+        Some(data) => Table::from_data_section(data),
+    };
 
-    let (cyls, tors) = extract_cyls(&table, scale);
+    let (cyls, tors) = extract_cyls(&table, scale)?;
 
     let cyls_no_dubs = MainCylinder::remove_dublicates(&cyls);
     let cyls_merged = MainCylinder::merge(&cyls_no_dubs);
     let bend_toros_no_dublicates: Vec<BendToro> = BendToro::remove_dublicates(&tors);
     let merged_tors = BendToro::merge(&bend_toros_no_dublicates);
     let racalculated_tors: Vec<BendToro> = recalc_tors_tole(&cyls_merged, &merged_tors);
-    let lracmd: Vec<LRACLR> = find_bending_surface(&cyls_merged, &racalculated_tors,&table,scale);
-
-    lracmd
+    let bodies: Vec<PipeBody> = find_pipe_bodies(&cyls_merged, &racalculated_tors,&table,scale)?;
 
+    if bodies.iter().any(|b| b.segments.is_empty()) {
+        Err(StepAnalysisError::EmptyProgram)
+    } else {
+        Ok(bodies)
+    }
 }
 
-pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendToro>) {
+pub fn extract_cyls(table: &Table, scale: f64) -> Result<(Vec<MainCylinder>, Vec<BendToro>), StepAnalysisError> {
     let mut toros: Vec<BendToro> = vec![];
     let mut cilinders: Vec<MainCylinder> = vec![];
+    let mut first_err: Option<StepAnalysisError> = None;
 
     table.shell.iter().for_each(|(k, v)| {
         let mut counter = 0;
         v.cfs_faces.iter().for_each(|face_holder| {
             let mut points: Vec<Point3> = vec![];
             let mut candidates: Vec<(MainCircle)> = vec![];
+            let mut surface_id: u64 = 0;
             match face_holder {
                 PlaceHolder::Ref(name) => {
                     let id = name_to_id(name.clone());
                     match table.face_surface.get(&id) {
                         None => {}
                         Some(face_holder) => {
+                            match &face_holder.face_geometry {
+                                PlaceHolder::Ref(name) => { surface_id = name_to_id(name.clone()); }
+                                PlaceHolder::Owned(_) => {}
+                            }
 
                             let face_bounds: &Vec<PlaceHolder<FaceBoundHolder>> = &face_holder.bounds;
                             //warn!("{:?}",face_bounds.len());
@@ -1603,8 +1699,13 @@ pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendTo
                                                                                             match table.edge_curve.get(&name_to_id(name.clone())) {
                                                                                                 None => {}
                                                                                                 Some(c) => {
-                                                                                                    let sp = extract_vertex(&table, &c.edge_start, scale).unwrap();
-                                                                                                    let ep = extract_vertex(&table, &c.edge_end, scale).unwrap();
+                                                                                                    let (sp, ep) = match (extract_vertex(&table, &c.edge_start, scale), extract_vertex(&table, &c.edge_end, scale)) {
+                                                                                                        (Some(sp), Some(ep)) => (sp, ep),
+                                                                                                        _ => {
+                                                                                                            skip_entity(&mut first_err, StepAnalysisError::MissingEntity { entity: "VERTEX_POINT", id: name_to_id(name.clone()) });
+                                                                                                            return;
+                                                                                                        }
+                                                                                                    };
                                                                                                     points.push(sp.clone());
                                                                                                     points.push(ep.clone());
 
@@ -1620,13 +1721,19 @@ pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendTo
                                                                                                                     found = true;
                                                                                                                     let circle_r = (circle.radius * scale);
 
-                                                                                                                    let (loc, dir, dir_rad) = extract_position(&table, &circle.position, scale);
+                                                                                                                    let (loc, dir, dir_rad) = match extract_position(&table, &circle.position, scale) {
+                                                                                                                        (Some(loc), Some(dir), Some(dir_rad)) => (loc, dir, dir_rad),
+                                                                                                                        _ => {
+                                                                                                                            skip_entity(&mut first_err, StepAnalysisError::MissingEntity { entity: "AXIS2_PLACEMENT_3D", id: curve_id.clone() });
+                                                                                                                            return;
+                                                                                                                        }
+                                                                                                                    };
                                                                                                                     let mc: MainCircle = MainCircle {
                                                                                                                         id: curve_id.clone(),
                                                                                                                         radius: circle_r,
-                                                                                                                        loc: loc.unwrap().clone(),
-                                                                                                                        dir: dir.unwrap().normalize(),
-                                                                                                                        radius_dir: dir_rad.unwrap().normalize(),
+                                                                                                                        loc: loc.clone(),
+                                                                                                                        dir: dir.normalize(),
+                                                                                                                        radius_dir: dir_rad.normalize(),
                                                                                                                         r_gr_id: (round_by_dec(circle_r, 5) * DIVIDER) as u64,
                                                                                                                     };
                                                                                                                    // borderlines.extend_from_slice(mc.gen_lines(&sp,&ep,otientation).as_slice());
@@ -1647,7 +1754,13 @@ pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendTo
                                                                                                                                 NonRationalBSplineCurveHolder::BSplineCurveWithKnots(spline) => {
                                                                                                                                     let mut contrl_points: Vec<CartesianPoint> = vec![];
                                                                                                                                     spline.control_points_list.iter().for_each(|cp| {
-                                                                                                                                        let pnt = extract_cartesian_point(&table, &cp, scale).unwrap();
+                                                                                                                                        let pnt = match extract_cartesian_point(&table, &cp, scale) {
+                                                                                                                                            Some(pnt) => pnt,
+                                                                                                                                            None => {
+                                                                                                                                                skip_entity(&mut first_err, StepAnalysisError::MissingEntity { entity: "CARTESIAN_POINT", id: curve_id.clone() });
+                                                                                                                                                return;
+                                                                                                                                            }
+                                                                                                                                        };
                                                                                                                                         let pp = CartesianPoint {
                                                                                                                                             label: "".to_string(),
                                                                                                                                             coordinates: Vec::from([pnt.x, pnt.y, pnt.z]),
@@ -1666,8 +1779,13 @@ pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendTo
                                                                                                                                         knots: spline.knots.clone(),
                                                                                                                                         knot_spec: spline.knot_spec.clone(),
                                                                                                                                     };
-                                                                                                                                    let bsc: BSplineCurve<Point3> = (&sspl).try_into().unwrap();
-                                                                                                                                    let res: NurbsCurve<Vector4> = NurbsCurve::try_from_bspline_and_weights(bsc, wgts.clone()).unwrap();
+                                                                                                                                    let res: NurbsCurve<Vector4> = match (&sspl).try_into().map_err(|e| format!("{:?}", e)).and_then(|bsc: BSplineCurve<Point3>| NurbsCurve::try_from_bspline_and_weights(bsc, wgts.clone()).map_err(|e| format!("{:?}", e))) {
+                                                                                                                                        Ok(res) => res,
+                                                                                                                                        Err(reason) => {
+                                                                                                                                            skip_entity(&mut first_err, StepAnalysisError::InvalidGeometry { entity: "RATIONAL_B_SPLINE_CURVE", id: curve_id.clone(), reason });
+                                                                                                                                            return;
+                                                                                                                                        }
+                                                                                                                                    };
                                                                                                                                     let mut tess_points: Vec<Point3> = vec![];
                                                                                                                                     for t in (0..=10) {
                                                                                                                                         let tess_point = res.subs(t as f64 / 10.0);
@@ -1708,7 +1826,13 @@ pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendTo
                                                                                                                     found = true;
                                                                                                                     let mut contrl_points: Vec<CartesianPoint> = vec![];
                                                                                                                     spline.control_points_list.iter().for_each(|cp| {
-                                                                                                                        let pnt = extract_cartesian_point(&table, &cp, scale).unwrap();
+                                                                                                                        let pnt = match extract_cartesian_point(&table, &cp, scale) {
+                                                                                                                            Some(pnt) => pnt,
+                                                                                                                            None => {
+                                                                                                                                skip_entity(&mut first_err, StepAnalysisError::MissingEntity { entity: "CARTESIAN_POINT", id: curve_id.clone() });
+                                                                                                                                return;
+                                                                                                                            }
+                                                                                                                        };
                                                                                                                         let pp = CartesianPoint {
                                                                                                                             label: "".to_string(),
                                                                                                                             coordinates: Vec::from([pnt.x, pnt.y, pnt.z]),
@@ -1728,7 +1852,13 @@ pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendTo
                                                                                                                         knot_spec: spline.knot_spec.clone(),
                                                                                                                     };
 
-                                                                                                                    let res: BSplineCurve<Point3> = (&sspl).try_into().unwrap();
+                                                                                                                    let res: BSplineCurve<Point3> = match (&sspl).try_into() {
+                                                                                                                        Ok(res) => res,
+                                                                                                                        Err(e) => {
+                                                                                                                            skip_entity(&mut first_err, StepAnalysisError::InvalidGeometry { entity: "B_SPLINE_CURVE_WITH_KNOTS", id: curve_id.clone(), reason: format!("{:?}", e) });
+                                                                                                                            return;
+                                                                                                                        }
+                                                                                                                    };
                                                                                                                     let mut tess_points: Vec<Point3> = vec![];
 
 
@@ -1755,14 +1885,20 @@ pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendTo
                                                                                                                 None => {}
                                                                                                                 Some(ellipse) => {
                                                                                                                     found = true;
-                                                                                                                    let (loc, dir, dir_rad) = extract_position(&table, &ellipse.position, scale);
+                                                                                                                    let (loc, dir, dir_rad) = match extract_position(&table, &ellipse.position, scale) {
+                                                                                                                        (Some(loc), Some(dir), Some(dir_rad)) => (loc, dir, dir_rad),
+                                                                                                                        _ => {
+                                                                                                                            skip_entity(&mut first_err, StepAnalysisError::MissingEntity { entity: "AXIS2_PLACEMENT_3D", id: curve_id.clone() });
+                                                                                                                            return;
+                                                                                                                        }
+                                                                                                                    };
 
                                                                                                                     let axe1 = ellipse.semi_axis_1;
                                                                                                                     let axe2 = ellipse.semi_axis_2;
 
-                                                                                                                    let dir_v=dir.unwrap().cross(dir_rad.unwrap()).normalize();
-                                                                                                                    let dir_u=dir_rad.unwrap().normalize();
-                                                                                                                    let c: Point3 =loc.unwrap();
+                                                                                                                    let dir_v=dir.cross(dir_rad).normalize();
+                                                                                                                    let dir_u=dir_rad.normalize();
+                                                                                                                    let c: Point3 =loc;
 
                                                                                                                     let steps: Vec<f64> = (0..=(2.0 * PI / 0.1) as usize)
                                                                                                                         .map(|i| i as f64 * 0.1)
@@ -1824,6 +1960,15 @@ pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendTo
                 }
                 PlaceHolder::Owned(_) => {}
             };
+            //Exact geometry from CYLINDRICAL_SURFACE / TOROIDAL_SURFACE, edge heuristics below are the fallback
+            match extract_analytic_surface(&table, surface_id, &points, &candidates, scale) {
+                None => {}
+                Some((cyls, tors)) => {
+                    cilinders.extend(cyls);
+                    toros.extend(tors);
+                    return;
+                }
+            }
             let mut no_dubs = remove_circle_dublicates(&candidates);
             no_dubs.sort_by(|a, b| a.r_gr_id.cmp(&b.r_gr_id));
             counter = counter + 1;
@@ -1858,10 +2003,160 @@ pub fn extract_cyls(table: &Table, scale: f64) -> (Vec<MainCylinder>, Vec<BendTo
         });
     });
 
-    (cilinders, toros)
+    //Bad entities outside the pipe are only logged, the error matters when nothing was found
+    if (cilinders.is_empty() && toros.is_empty()) {
+        if let Some(e) = first_err {
+            return Err(e);
+        }
+    }
+    Ok((cilinders, toros))
+}
+//Entities that fail to convert are logged and skipped, the first one is kept for the report
+fn skip_entity(first_err: &mut Option<StepAnalysisError>, e: StepAnalysisError) {
+    warn!("skipped {}", e);
+    first_err.get_or_insert(e);
+}
+fn placement(table: &Table, pos: &PlaceHolder<Axis2PlacementHolder>, scale: f64, id: u64) -> Result<(Point3, Vector3, Vector3), StepAnalysisError> {
+    match extract_position(table, pos, scale) {
+        (Some(loc), Some(dir), Some(dir_rad)) => Ok((loc, dir, dir_rad)),
+        _ => Err(StepAnalysisError::MissingEntity { entity: "AXIS2_PLACEMENT_3D", id: id }),
+    }
+}
+fn control_points(table: &Table, list: &[PlaceHolder<CartesianPointHolder>], scale: f64, id: u64) -> Result<Vec<CartesianPoint>, StepAnalysisError> {
+    list.iter().map(|cp| {
+        let pnt = extract_cartesian_point(table, cp, scale).ok_or(StepAnalysisError::MissingEntity { entity: "CARTESIAN_POINT", id: id })?;
+        Ok(CartesianPoint {
+            label: "".to_string(),
+            coordinates: Vec::from([pnt.x, pnt.y, pnt.z]),
+        })
+    }).collect()
 }
-pub fn extract_tors(table: &Table, scale: f64, cyls: &Vec<MainCylinder>, radius: f64) -> Vec<BendToro> {
+pub fn extract_analytic_surface(table: &Table, surface_id: u64, points: &Vec<Point3>, circles: &Vec<MainCircle>, scale: f64) -> Option<(Vec<MainCylinder>, Vec<BendToro>)> {
+    if (points.is_empty()) {
+        return None;
+    }
+    match table.cylindrical_surface.get(&surface_id) {
+        None => {}
+        Some(surf) => {
+            let r = surf.radius * scale;
+            let (loc, dir, dir_ref) = match extract_position3d(table, &surf.position, scale) {
+                (Some(loc), Some(dir), Some(dir_ref)) => (loc, dir.normalize(), dir_ref.normalize()),
+                _ => return None,
+            };
+            let mut t_min = f64::MAX;
+            let mut t_max = f64::MIN;
+            points.iter().for_each(|p| {
+                let t = p.sub(loc).dot(dir);
+                if (t < t_min) { t_min = t; }
+                if (t > t_max) { t_max = t; }
+            });
+            if (t_max - t_min < TOLE) {
+                return None;
+            }
+            let r_gr_id = (round_by_dec(r, 5) * DIVIDER) as u64;
+            let ca = MainCircle {
+                id: rand::thread_rng().gen_range(0..1024),
+                radius: r,
+                loc: loc + dir * t_max,
+                dir: dir,
+                radius_dir: dir_ref,
+                r_gr_id: r_gr_id,
+            };
+            let cb = MainCircle {
+                id: rand::thread_rng().gen_range(0..1024),
+                radius: r,
+                loc: loc + dir * t_min,
+                dir: dir,
+                radius_dir: dir_ref,
+                r_gr_id: r_gr_id,
+            };
+            let nc = MainCylinder {
+                id: rand::thread_rng().gen_range(0..1024),
+                ca: ca,
+                cb: cb,
+                h: t_max - t_min,
+                r: r,
+                r_gr_id: r_gr_id,
+                ca_tor: u64::MAX,
+                cb_tor: u64::MAX,
+                t: -1,
+            };
+            return Some((vec![nc], vec![]));
+        }
+    }
+    match table.toroidal_surface.get(&surface_id) {
+        None => {}
+        Some(surf) => {
+            let bend_radius = surf.major_radius * scale;
+            let r = surf.minor_radius * scale;
+            if (bend_radius < r || bend_radius > MAX_BEND_RADIUS) {
+                return None;
+            }
+            let (center, plane, dir_ref) = match extract_position3d(table, &surf.position, scale) {
+                (Some(loc), Some(dir), Some(dir_ref)) => (loc, dir.normalize(), dir_ref.normalize()),
+                _ => return None,
+            };
+            let dir_ref_90 = plane.cross(dir_ref).normalize();
+            //Boundary points and end circle centers projected to the bend centerline, as angles around the torus axis
+            let mut angles: Vec<f64> = vec![];
+            points.iter().chain(circles.iter().filter(|c| (c.radius - r).abs() < TOLE).map(|c| &c.loc)).for_each(|p| {
+                let v = p.sub(center);
+                let v_plane = v - plane * v.dot(plane);
+                if (v_plane.magnitude() > TOLE) {
+                    let angle = v_plane.dot(dir_ref_90).atan2(v_plane.dot(dir_ref));
+                    angles.push(if (angle < 0.0) { angle + 2.0 * PI } else { angle });
+                }
+            });
+            if (angles.len() < 2) {
+                return None;
+            }
+            angles.sort_by(|a, b| a.total_cmp(b));
+            //The bend is the arc opposite to the largest angular gap
+            let mut gap = angles[0] + 2.0 * PI - angles[angles.len() - 1];
+            let mut start_angle = angles[0];
+            let mut end_angle = angles[angles.len() - 1];
+            angles.windows(2).for_each(|w| {
+                if (w[1] - w[0] > gap) {
+                    gap = w[1] - w[0];
+                    start_angle = w[1];
+                    end_angle = w[0];
+                }
+            });
+            if (gap < TOLE / bend_radius || 2.0 * PI - gap < TOLE / bend_radius) {
+                return None;
+            }
+            let r_gr_id = (round_by_dec(r, 5) * DIVIDER) as u64;
+            let gen_circle = |angle: f64| -> MainCircle {
+                let radius_dir = (dir_ref * angle.cos() + dir_ref_90 * angle.sin()).normalize();
+                MainCircle {
+                    id: rand::thread_rng().gen_range(0..1024),
+                    radius: r,
+                    loc: center + radius_dir * bend_radius,
+                    dir: radius_dir.cross(plane),
+                    radius_dir: radius_dir,
+                    r_gr_id: r_gr_id,
+                }
+            };
+            let t = BendToro {
+                id: rand::thread_rng().gen_range(0..1024),
+                r: r,
+                bend_radius: bend_radius,
+                bend_center_point: center,
+                bend_plane_norm: plane,
+                radius_dir: plane,
+                ca: gen_circle(start_angle),
+                cb: gen_circle(end_angle),
+                r_gr_id: r_gr_id,
+                t: -1,
+            };
+            return Some((vec![], vec![t]));
+        }
+    }
+    None
+}
+pub fn extract_tors(table: &Table, scale: f64, cyls: &Vec<MainCylinder>, radius: f64) -> Result<Vec<BendToro>, StepAnalysisError> {
     let mut toros: Vec<BendToro> = vec![];
+    let mut first_err: Option<StepAnalysisError> = None;
 
     table.shell.iter().for_each(|(k, v)| {
         let mut counter = 0;
@@ -1903,188 +2198,179 @@ pub fn extract_tors(table: &Table, scale: f64, cyls: &Vec<MainCylinder>, radius:
                                                                                             match table.edge_curve.get(&name_to_id(name.clone())) {
                                                                                                 None => {}
                                                                                                 Some(c) => {
-                                                                                                    let curve_geom: &PlaceHolder<CurveAnyHolder> = &c.edge_geometry;
-                                                                                                    match curve_geom {
-                                                                                                        PlaceHolder::Ref(name) => {
-                                                                                                            let curve_id = &name_to_id(name.clone());
-                                                                                                            let mut found = false;
-                                                                                                            match table.circle.get(curve_id) {
-                                                                                                                None => {}
-                                                                                                                Some(circle) => {
-                                                                                                                    found = true;
-                                                                                                                    let circle_r = circle.radius * scale;
-                                                                                                                    if (abs(circle_r - radius) < TOLE) {
-                                                                                                                        let (loc, dir, dir_rad) = extract_position(&table, &circle.position, scale);
-                                                                                                                        let mc: MainCircle = MainCircle {
-                                                                                                                            id: curve_id.clone(),
-                                                                                                                            radius: radius,
-                                                                                                                            loc: loc.unwrap().clone(),
-                                                                                                                            dir: dir.unwrap().normalize(),
-                                                                                                                            radius_dir: dir_rad.unwrap().normalize(),
-                                                                                                                            r_gr_id: (round_by_dec(radius, 5) * DIVIDER) as u64,
-                                                                                                                        };
-                                                                                                                        candidates.push(mc);
+                                                                                                    //Entities that fail to convert skip the edge, see skip_entity
+                                                                                                    let edge: Result<(), StepAnalysisError> = (|| {
+                                                                                                        let curve_geom: &PlaceHolder<CurveAnyHolder> = &c.edge_geometry;
+                                                                                                        match curve_geom {
+                                                                                                            PlaceHolder::Ref(name) => {
+                                                                                                                let curve_id = &name_to_id(name.clone());
+                                                                                                                let mut found = false;
+                                                                                                                match table.circle.get(curve_id) {
+                                                                                                                    None => {}
+                                                                                                                    Some(circle) => {
+                                                                                                                        found = true;
+                                                                                                                        let circle_r = circle.radius * scale;
+                                                                                                                        if (abs(circle_r - radius) < TOLE) {
+                                                                                                                            let (loc, dir, dir_rad) = placement(&table, &circle.position, scale, *curve_id)?;
+                                                                                                                            let mc: MainCircle = MainCircle {
+                                                                                                                                id: curve_id.clone(),
+                                                                                                                                radius: radius,
+                                                                                                                                loc: loc.clone(),
+                                                                                                                                dir: dir.normalize(),
+                                                                                                                                radius_dir: dir_rad.normalize(),
+                                                                                                                                r_gr_id: (round_by_dec(radius, 5) * DIVIDER) as u64,
+                                                                                                                            };
+                                                                                                                            candidates.push(mc);
+                                                                                                                        }
                                                                                                                     }
                                                                                                                 }
-                                                                                                            }
-                                                                                                            match table.rational_b_spline_curve.get(curve_id) {
-                                                                                                                None => {}
-                                                                                                                Some(spline) => {
-                                                                                                                    found = true;
-                                                                                                                    let wgts = &spline.weights_data;
-
-                                                                                                                    //warn!("C {:?}",b_spline_curve_with_knots.l);
-                                                                                                                    match &spline.non_rational_b_spline_curve {
-                                                                                                                        PlaceHolder::Ref(name) => {}
-                                                                                                                        PlaceHolder::Owned(v) => {
-                                                                                                                            match v {
-                                                                                                                                NonRationalBSplineCurveHolder::BSplineCurveWithKnots(spline) => {
-                                                                                                                                    let mut contrl_points: Vec<CartesianPoint> = vec![];
-                                                                                                                                    spline.control_points_list.iter().for_each(|cp| {
-                                                                                                                                        let pnt = extract_cartesian_point(&table, &cp, scale).unwrap();
-                                                                                                                                        let pp = CartesianPoint {
+                                                                                                                match table.rational_b_spline_curve.get(curve_id) {
+                                                                                                                    None => {}
+                                                                                                                    Some(spline) => {
+                                                                                                                        found = true;
+                                                                                                                        let wgts = &spline.weights_data;
+
+                                                                                                                        //warn!("C {:?}",b_spline_curve_with_knots.l);
+                                                                                                                        match &spline.non_rational_b_spline_curve {
+                                                                                                                            PlaceHolder::Ref(name) => {}
+                                                                                                                            PlaceHolder::Owned(v) => {
+                                                                                                                                match v {
+                                                                                                                                    NonRationalBSplineCurveHolder::BSplineCurveWithKnots(spline) => {
+                                                                                                                                        let contrl_points: Vec<CartesianPoint> = control_points(&table, &spline.control_points_list, scale, *curve_id)?;
+
+                                                                                                                                        let sspl: BSplineCurveWithKnots = BSplineCurveWithKnots {
                                                                                                                                             label: "".to_string(),
-                                                                                                                                            coordinates: Vec::from([pnt.x, pnt.y, pnt.z]),
+                                                                                                                                            degree: spline.degree,
+                                                                                                                                            control_points_list: contrl_points,
+                                                                                                                                            curve_form: spline.curve_form.clone(),
+                                                                                                                                            closed_curve: spline.closed_curve,
+                                                                                                                                            self_intersect: spline.self_intersect,
+                                                                                                                                            knot_multiplicities: spline.knot_multiplicities.clone(),
+                                                                                                                                            knots: spline.knots.clone(),
+                                                                                                                                            knot_spec: spline.knot_spec.clone(),
                                                                                                                                         };
-                                                                                                                                        contrl_points.push(pp);
-                                                                                                                                    });
-
-                                                                                                                                    let sspl: BSplineCurveWithKnots = BSplineCurveWithKnots {
-                                                                                                                                        label: "".to_string(),
-                                                                                                                                        degree: spline.degree,
-                                                                                                                                        control_points_list: contrl_points,
-                                                                                                                                        curve_form: spline.curve_form.clone(),
-                                                                                                                                        closed_curve: spline.closed_curve,
-                                                                                                                                        self_intersect: spline.self_intersect,
-                                                                                                                                        knot_multiplicities: spline.knot_multiplicities.clone(),
-                                                                                                                                        knots: spline.knots.clone(),
-                                                                                                                                        knot_spec: spline.knot_spec.clone(),
-                                                                                                                                    };
-                                                                                                                                    let bsc: BSplineCurve<Point3> = (&sspl).try_into().unwrap();
-                                                                                                                                    let res: NurbsCurve<Vector4> = NurbsCurve::try_from_bspline_and_weights(bsc, wgts.clone()).unwrap();
-                                                                                                                                    let mut tess_points: Vec<Point3> = vec![];
-                                                                                                                                    for t in (0..=10) {
-                                                                                                                                        let tess_point = res.subs(t as f64 / 10.0);
-                                                                                                                                        tess_points.push(tess_point);
-                                                                                                                                        //points.push(tess_point);
-                                                                                                                                    }
-                                                                                                                                    if tess_points.len() > 10 {
-                                                                                                                                        let c = circum_center(tess_points[0], tess_points[2], tess_points[5]);
-                                                                                                                                        let r = c.sub(tess_points[0]).magnitude();
-
-                                                                                                                                        if (abs(r - radius) < TOLE) {
-                                                                                                                                            let mc: MainCircle = MainCircle {
-                                                                                                                                                id: curve_id.clone(),
-                                                                                                                                                radius: radius,
-                                                                                                                                                loc: c,
-                                                                                                                                                dir: truck_geometry::prelude::Plane::new(tess_points[0], tess_points[5], tess_points[7]).normal().normalize(),
-                                                                                                                                                radius_dir: c.sub(tess_points[0]).normalize(),
-                                                                                                                                                r_gr_id: (round_by_dec(radius, 5) * DIVIDER) as u64,
-                                                                                                                                            };
-                                                                                                                                            candidates.push(mc);
-                                                                                                                                        } else {
-                                                                                                                                            points.extend(tess_points)
+                                                                                                                                        let bsc: BSplineCurve<Point3> = (&sspl).try_into().map_err(|e| StepAnalysisError::InvalidGeometry { entity: "RATIONAL_B_SPLINE_CURVE", id: *curve_id, reason: format!("{:?}", e) })?;
+                                                                                                                                        let res: NurbsCurve<Vector4> = NurbsCurve::try_from_bspline_and_weights(bsc, wgts.clone()).map_err(|e| StepAnalysisError::InvalidGeometry { entity: "RATIONAL_B_SPLINE_CURVE", id: *curve_id, reason: format!("{:?}", e) })?;
+                                                                                                                                        let mut tess_points: Vec<Point3> = vec![];
+                                                                                                                                        for t in (0..=10) {
+                                                                                                                                            let tess_point = res.subs(t as f64 / 10.0);
+                                                                                                                                            tess_points.push(tess_point);
+                                                                                                                                            //points.push(tess_point);
+                                                                                                                                        }
+                                                                                                                                        if tess_points.len() > 10 {
+                                                                                                                                            let c = circum_center(tess_points[0], tess_points[2], tess_points[5]);
+                                                                                                                                            let r = c.sub(tess_points[0]).magnitude();
+
+                                                                                                                                            if (abs(r - radius) < TOLE) {
+                                                                                                                                                let mc: MainCircle = MainCircle {
+                                                                                                                                                    id: curve_id.clone(),
+                                                                                                                                                    radius: radius,
+                                                                                                                                                    loc: c,
+                                                                                                                                                    dir: truck_geometry::prelude::Plane::new(tess_points[0], tess_points[5], tess_points[7]).normal().normalize(),
+                                                                                                                                                    radius_dir: c.sub(tess_points[0]).normalize(),
+                                                                                                                                                    r_gr_id: (round_by_dec(radius, 5) * DIVIDER) as u64,
+                                                                                                                                                };
+                                                                                                                                                candidates.push(mc);
+                                                                                                                                            } else {
+                                                                                                                                                points.extend(tess_points)
+                                                                                                                                            }
                                                                                                                                         }
                                                                                                                                     }
-                                                                                                                                }
-                                                                                                                                NonRationalBSplineCurveHolder::BezierCurve(c) => {
-                                                                                                                                    warn!("BezierCurve");
-                                                                                                                                }
-                                                                                                                                NonRationalBSplineCurveHolder::QuasiUniformCurve(c) => {
-                                                                                                                                    warn!("QuasiUniformCurve");
-                                                                                                                                }
-                                                                                                                                NonRationalBSplineCurveHolder::UniformCurve(c) => {
-                                                                                                                                    warn!("UniformCurve");
+                                                                                                                                    NonRationalBSplineCurveHolder::BezierCurve(c) => {
+                                                                                                                                        warn!("BezierCurve");
+                                                                                                                                    }
+                                                                                                                                    NonRationalBSplineCurveHolder::QuasiUniformCurve(c) => {
+                                                                                                                                        warn!("QuasiUniformCurve");
+                                                                                                                                    }
+                                                                                                                                    NonRationalBSplineCurveHolder::UniformCurve(c) => {
+                                                                                                                                        warn!("UniformCurve");
+                                                                                                                                    }
                                                                                                                                 }
                                                                                                                             }
                                                                                                                         }
                                                                                                                     }
                                                                                                                 }
-                                                                                                            }
-                                                                                                            match table.b_spline_curve_with_knots.get(curve_id) {
-                                                                                                                None => {}
-                                                                                                                Some(spline) => {
-                                                                                                                    found = true;
-                                                                                                                    let mut contrl_points: Vec<CartesianPoint> = vec![];
-                                                                                                                    spline.control_points_list.iter().for_each(|cp| {
-                                                                                                                        let pnt = extract_cartesian_point(&table, &cp, scale).unwrap();
-                                                                                                                        let pp = CartesianPoint {
+                                                                                                                match table.b_spline_curve_with_knots.get(curve_id) {
+                                                                                                                    None => {}
+                                                                                                                    Some(spline) => {
+                                                                                                                        found = true;
+                                                                                                                        let contrl_points: Vec<CartesianPoint> = control_points(&table, &spline.control_points_list, scale, *curve_id)?;
+
+                                                                                                                        let sspl: BSplineCurveWithKnots = BSplineCurveWithKnots {
                                                                                                                             label: "".to_string(),
-                                                                                                                            coordinates: Vec::from([pnt.x, pnt.y, pnt.z]),
+                                                                                                                            degree: spline.degree,
+                                                                                                                            control_points_list: contrl_points,
+                                                                                                                            curve_form: spline.curve_form.clone(),
+                                                                                                                            closed_curve: spline.closed_curve,
+                                                                                                                            self_intersect: spline.self_intersect,
+                                                                                                                            knot_multiplicities: spline.knot_multiplicities.clone(),
+                                                                                                                            knots: spline.knots.clone(),
+                                                                                                                            knot_spec: spline.knot_spec.clone(),
                                                                                                                         };
-                                                                                                                        contrl_points.push(pp);
-                                                                                                                    });
 
-                                                                                                                    let sspl: BSplineCurveWithKnots = BSplineCurveWithKnots {
-                                                                                                                        label: "".to_string(),
-                                                                                                                        degree: spline.degree,
-                                                                                                                        control_points_list: contrl_points,
-                                                                                                                        curve_form: spline.curve_form.clone(),
-                                                                                                                        closed_curve: spline.closed_curve,
-                                                                                                                        self_intersect: spline.self_intersect,
-                                                                                                                        knot_multiplicities: spline.knot_multiplicities.clone(),
-                                                                                                                        knots: spline.knots.clone(),
-                                                                                                                        knot_spec: spline.knot_spec.clone(),
-                                                                                                                    };
-
-                                                                                                                    let res: BSplineCurve<Point3> = (&sspl).try_into().unwrap();
-                                                                                                                    let mut tess_points: Vec<Point3> = vec![];
-                                                                                                                    for t in (0..=10) {
-                                                                                                                        let tess_point = res.subs(t as f64 / 10.0);
-                                                                                                                        tess_points.push(tess_point);
-                                                                                                                        //points.push(tess_point);
-                                                                                                                    }
-                                                                                                                    if tess_points.len() > 10 {
-                                                                                                                        let c = circum_center(tess_points[0], tess_points[2], tess_points[5]);
-                                                                                                                        let r = c.sub(tess_points[0]).magnitude();
-                                                                                                                        if (abs(r - radius) < TOLE) {
-                                                                                                                            let mc: MainCircle = MainCircle {
-                                                                                                                                id: curve_id.clone(),
-                                                                                                                                radius: radius,
-                                                                                                                                loc: c,
-                                                                                                                                dir: truck_geometry::prelude::Plane::new(tess_points[0], tess_points[5], tess_points[7]).normal().normalize(),
-                                                                                                                                radius_dir: c.sub(tess_points[0]).normalize(),
-                                                                                                                                r_gr_id: (round_by_dec(radius, 5) * DIVIDER) as u64,
-                                                                                                                            };
-                                                                                                                            candidates.push(mc);
-                                                                                                                        } else {
-                                                                                                                            points.extend(tess_points)
+                                                                                                                        let res: BSplineCurve<Point3> = (&sspl).try_into().map_err(|e| StepAnalysisError::InvalidGeometry { entity: "B_SPLINE_CURVE_WITH_KNOTS", id: *curve_id, reason: format!("{:?}", e) })?;
+                                                                                                                        let mut tess_points: Vec<Point3> = vec![];
+                                                                                                                        for t in (0..=10) {
+                                                                                                                            let tess_point = res.subs(t as f64 / 10.0);
+                                                                                                                            tess_points.push(tess_point);
+                                                                                                                            //points.push(tess_point);
+                                                                                                                        }
+                                                                                                                        if tess_points.len() > 10 {
+                                                                                                                            let c = circum_center(tess_points[0], tess_points[2], tess_points[5]);
+                                                                                                                            let r = c.sub(tess_points[0]).magnitude();
+                                                                                                                            if (abs(r - radius) < TOLE) {
+                                                                                                                                let mc: MainCircle = MainCircle {
+                                                                                                                                    id: curve_id.clone(),
+                                                                                                                                    radius: radius,
+                                                                                                                                    loc: c,
+                                                                                                                                    dir: truck_geometry::prelude::Plane::new(tess_points[0], tess_points[5], tess_points[7]).normal().normalize(),
+                                                                                                                                    radius_dir: c.sub(tess_points[0]).normalize(),
+                                                                                                                                    r_gr_id: (round_by_dec(radius, 5) * DIVIDER) as u64,
+                                                                                                                                };
+                                                                                                                                candidates.push(mc);
+                                                                                                                            } else {
+                                                                                                                                points.extend(tess_points)
+                                                                                                                            }
                                                                                                                         }
                                                                                                                     }
                                                                                                                 }
-                                                                                                            }
-                                                                                                            match table.ellipse.get(curve_id) {
-                                                                                                                None => {}
-                                                                                                                Some(ellipse) => {
-                                                                                                                    found = true;
-                                                                                                                    let (loc, dir, dir_rad) = extract_position(&table, &ellipse.position, scale);
-                                                                                                                    let axe1 = ellipse.semi_axis_1;
-                                                                                                                    let axe2 = ellipse.semi_axis_1;
-                                                                                                                    let p1 = loc.unwrap() + dir_rad.unwrap() * axe1;
-                                                                                                                    let p2 = loc.unwrap() + dir_rad.unwrap() * axe2;
-                                                                                                                    points.push(p1);
-                                                                                                                    points.push(p2);
+                                                                                                                match table.ellipse.get(curve_id) {
+                                                                                                                    None => {}
+                                                                                                                    Some(ellipse) => {
+                                                                                                                        found = true;
+                                                                                                                        let (loc, dir, dir_rad) = placement(&table, &ellipse.position, scale, *curve_id)?;
+                                                                                                                        let axe1 = ellipse.semi_axis_1;
+                                                                                                                        let axe2 = ellipse.semi_axis_1;
+                                                                                                                        let p1 = loc + dir_rad * axe1;
+                                                                                                                        let p2 = loc + dir_rad * axe2;
+                                                                                                                        points.push(p1);
+                                                                                                                        points.push(p2);
+                                                                                                                    }
                                                                                                                 }
-                                                                                                            }
-                                                                                                            match table.line.get(curve_id) {
-                                                                                                                None => {}
-                                                                                                                Some(line) => {
-                                                                                                                    found = true;
+                                                                                                                match table.line.get(curve_id) {
+                                                                                                                    None => {}
+                                                                                                                    Some(line) => {
+                                                                                                                        found = true;
+                                                                                                                    }
                                                                                                                 }
-                                                                                                            }
 
-                                                                                                            if (!found) {
-                                                                                                                println!("name ID {:?} ", &name_to_id(name.clone()))
-                                                                                                            }
+                                                                                                                if (!found) {
+                                                                                                                    println!("name ID {:?} ", &name_to_id(name.clone()))
+                                                                                                                }
 
-                                                                                                            //println!("name ID {:?} ", &name_to_id(name.clone()))
+                                                                                                                //println!("name ID {:?} ", &name_to_id(name.clone()))
+                                                                                                            }
+                                                                                                            PlaceHolder::Owned(_) => {}
                                                                                                         }
-                                                                                                        PlaceHolder::Owned(_) => {}
+                                                                                                        let sp = extract_vertex(&table, &c.edge_start, scale).ok_or(StepAnalysisError::MissingEntity { entity: "VERTEX_POINT", id: name_to_id(name.clone()) })?;
+                                                                                                        let ep = extract_vertex(&table, &c.edge_end, scale).ok_or(StepAnalysisError::MissingEntity { entity: "VERTEX_POINT", id: name_to_id(name.clone()) })?;
+                                                                                                        //points.push(sp);
+                                                                                                        //points.push(ep);
+                                                                                                        Ok(())
+                                                                                                    })();
+                                                                                                    if let Err(e) = edge {
+                                                                                                        skip_entity(&mut first_err, e);
                                                                                                     }
-                                                                                                    let sp = extract_vertex(&table, &c.edge_start, scale).unwrap();
-                                                                                                    let ep = extract_vertex(&table, &c.edge_end, scale).unwrap();
-                                                                                                    //points.push(sp);
-                                                                                                    //points.push(ep);
                                                                                                 }
                                                                                             }
                                                                                         }
@@ -2207,7 +2493,13 @@ pub fn extract_tors(table: &Table, scale: f64, cyls: &Vec<MainCylinder>, radius:
             }
         });
     });
-    toros
+    //Bad entities outside the pipe are only logged, the error matters when nothing was found
+    if (toros.is_empty()) {
+        if let Some(e) = first_err {
+            return Err(e);
+        }
+    }
+    Ok(toros)
 }
 pub fn recalc_tors_tole(cyls: &Vec<MainCylinder>, tors: &Vec<BendToro>) -> Vec<BendToro> {
     let mut toros: Vec<BendToro> = vec![];
@@ -2600,17 +2892,22 @@ pub fn extract_cartesian_point(t: &Table, point: &PlaceHolder<CartesianPointHold
         PlaceHolder::Owned(_) => None,
     }
 }
-pub fn extract_vector(t: &Table, vector: &PlaceHolder<VectorHolder>, scale: f64, ) -> Option<Vector3> {
+pub fn extract_vector(t: &Table, vector: &PlaceHolder<VectorHolder>, scale: f64, ) -> Result<Vector3, StepAnalysisError> {
     match vector {
-        Ref(name) => match t.vector.get(&name_to_id(name.clone())) {
-            None => None,
-            Some(v) => {
-                let mag=v.magnitude;
-                let dir=extract_direction(t,&Some(v.orientation.clone()),scale).unwrap().normalize();
-                Some(dir.mul(mag))
+        Ref(name) => {
+            let id = name_to_id(name.clone());
+            match t.vector.get(&id) {
+                None => Err(StepAnalysisError::MissingEntity { entity: "VECTOR", id: id }),
+                Some(v) => {
+                    let mag=v.magnitude;
+                    match extract_direction(t,&Some(v.orientation.clone()),scale) {
+                        Some(dir) => Ok(dir.normalize().mul(mag)),
+                        None => Err(StepAnalysisError::MissingEntity { entity: "DIRECTION", id: id }),
+                    }
+                }
             }
         },
-        PlaceHolder::Owned(_) => None,
+        PlaceHolder::Owned(_) => Err(StepAnalysisError::InvalidGeometry { entity: "VECTOR", id: 0, reason: "inline VECTOR is not supported".to_string() }),
     }
 }
 pub fn extract_position(t: &Table, pos: &PlaceHolder<Axis2PlacementHolder>, scale: f64, ) -> (Option<Point3>, Option<Vector3>, Option<Vector3>) {
@@ -2735,7 +3032,47 @@ pub fn nurbs_to_circle(nurbs_points: &Vec<Point3>) -> Option<MainCircle> {
         None
     }
 }
-pub fn find_bending_surface(cyls: &Vec<MainCylinder>, tors: &Vec<(BendToro)>, table: &Table, scale: f64) -> Vec<LRACLR> {
+pub fn find_bending_surface(cyls: &Vec<MainCylinder>, tors: &Vec<(BendToro)>, table: &Table, scale: f64) -> Result<PipeBody, StepAnalysisError> {
+    let bodies = find_pipe_bodies(cyls, tors, table, scale)?;
+    let (body, warning) = select_main_body(&bodies)?;
+    if let Some(w) = warning {
+        warn!("{}", w);
+    }
+    Ok(body)
+}
+//Body with most segments, equal counts go to the largest diameter and then to the first found.
+//The returned AmbiguousRadiusGroup is a warning, the pick is still made
+pub fn select_main_body(bodies: &Vec<PipeBody>) -> Result<(PipeBody, Option<StepAnalysisError>), StepAnalysisError> {
+    let mut main_indx: Option<usize> = None;
+    bodies.iter().enumerate().for_each(|(i, b)| {
+        match main_indx {
+            None => { main_indx = Some(i); }
+            Some(mi) => {
+                let mb = &bodies[mi];
+                if (b.segments.len() > mb.segments.len() || (b.segments.len() == mb.segments.len() && b.outer_radius > mb.outer_radius)) {
+                    main_indx = Some(i);
+                }
+            }
+        }
+    });
+    match main_indx {
+        None => Err(StepAnalysisError::NoPipeBody),
+        Some(mi) => {
+            let mb = &bodies[mi];
+            let mut ambiguous: Vec<f64> = bodies.iter().enumerate()
+                .filter(|(i, b)| *i != mi && b.segments.len() == mb.segments.len())
+                .map(|(_, b)| b.outer_radius)
+                .collect();
+            if (ambiguous.is_empty()) {
+                Ok((mb.clone(), None))
+            } else {
+                ambiguous.insert(0, mb.outer_radius);
+                Ok((mb.clone(), Some(StepAnalysisError::AmbiguousRadiusGroup(ambiguous))))
+            }
+        }
+    }
+}
+pub fn find_pipe_bodies(cyls: &Vec<MainCylinder>, tors: &Vec<(BendToro)>, table: &Table, scale: f64) -> Result<Vec<PipeBody>, StepAnalysisError> {
     let mut cc: Vec<MainCylinder> = cyls.clone();
     let mut tt: Vec<BendToro> = tors.clone();
 
@@ -2752,67 +3089,121 @@ pub fn find_bending_surface(cyls: &Vec<MainCylinder>, tors: &Vec<(BendToro)>, ta
         let vec = v.into_iter().collect_vec();
         thm.insert(k, vec);
     });
-    let mut radius_gr: u64 = 0;
-    let mut op_qty: usize = 0;
 
+    //Every radius group may hold several independent pipes, each connected chain is a candidate body
+    let mut chains: Vec<(u64, Vec<MainCylinder>, Vec<BendToro>)> = vec![];
     chm.iter().for_each(|(k, v)| {
         match thm.get(k) {
             None => {}
             Some(tors_same_r) => {
-
-                if (v.len() == tors_same_r.len() + 1) {
-                    let ops= tors_same_r.len()+v.len();
-                    if(ops>op_qty){
-                            radius_gr = k.clone();
-                            op_qty=ops;
-                    }else if (ops==op_qty) {
-                        if (k.clone() > radius_gr) {
-                            radius_gr = k.clone();
-                            op_qty=ops;
-                        }
+                split_chains(v, tors_same_r).into_iter().for_each(|(c, t)| {
+                    if (!t.is_empty() && c.len() == t.len() + 1) {
+                        chains.push((k.clone(), c, t));
                     }
-                    //warn!("T {:?} C {:?} GR {:?} R {:?} OP {:?}", tors_same_r.len(), v.len(),k,radius_gr,op_qty);
-                }
-
+                });
             }
         }
     });
-    let mut ncyls: Vec<MainCylinder> = vec![];
-    let mut ntors: Vec<(BendToro)> = vec![];
-    if (radius_gr > 0) {
-        //let mut allpts: Vec<Point3> = vec![];
-        match chm.get(&radius_gr) {
-            None => {}
-            Some(c) => {
-                c.iter().for_each(|nc| {
-                    //allpts.extend(nc.gen_points());
-                    //warn!("CYL R {:?}  {:?}  {:?}", nc.r, nc.ca.radius, nc.cb.radius);
-                    //export_to_pt_str(&nc.gen_points(), nc.id.to_string().as_str());
-                    ncyls.push(nc.clone().clone());
-                });
+    chains.sort_by(|(ka, ca, _), (kb, cb, _)| kb.cmp(ka).then(cb.len().cmp(&ca.len())));
+
+    let mut first_err: Option<StepAnalysisError> = None;
+    let mut bodies: Vec<(PipeBody, Vec<MainCylinder>)> = vec![];
+    chains.iter().for_each(|(k, c, t)| {
+        match find_next_tor(c, t, table, scale) {
+            Ok(segments) => {
+                bodies.push((PipeBody {
+                    segments: segments,
+                    outer_radius: c[0].r,
+                    inner_radius: None,
+                }, c.clone()));
+            }
+            Err(e) => {
+                warn!("chain R {:?} skipped {}", *k as f64 / DIVIDER, e);
+                first_err.get_or_insert(e);
             }
         }
-        match thm.get(&radius_gr) {
-            None => {}
-            Some(c) => {
-                c.iter().for_each(|nc| {
-                    //allpts.extend(nc.gen_points());
-                    //warn!("TOR R {:?}  {:?}  {:?}", nc.r, nc.ca.radius, nc.cb.radius);
-                    //export_to_pt_str(&nc.gen_points(), nc.id.to_string().as_str());
-                    ntors.push(nc.clone().clone());
-                });
+    });
+
+    //Hollow tube: inner wall is the closest smaller radius chain on the same centerline
+    let mut is_inner: Vec<bool> = vec![false; bodies.len()];
+    for i in 0..bodies.len() {
+        if (is_inner[i]) {
+            continue;
+        }
+        for j in 0..bodies.len() {
+            if (i != j && !is_inner[j] && bodies[j].0.outer_radius < bodies[i].0.outer_radius && is_same_centerline(&bodies[j].1, &bodies[i].1)) {
+                let is_closer = match bodies[i].0.inner_radius {
+                    None => true,
+                    Some(r) => bodies[j].0.outer_radius > r,
+                };
+                if (is_closer) {
+                    bodies[i].0.inner_radius = Some(bodies[j].0.outer_radius);
+                    is_inner[j] = true;
+                }
             }
         }
-        // export_to_pt_str(&allpts, "dada");
     }
+    let ret: Vec<PipeBody> = bodies.into_iter().enumerate()
+        .filter(|(i, _)| !is_inner[*i])
+        .map(|(_, (b, _))| b)
+        .collect();
 
-
-    let lracmd: Vec<LRACLR> = find_next_tor(&ncyls, &ntors,table,scale);
-
-    //(ncyls, ntors)
-    lracmd
+    if (ret.is_empty()) {
+        Err(first_err.unwrap_or(StepAnalysisError::NoPipeBody))
+    } else {
+        Ok(ret)
+    }
 }
-fn find_next_tor(_cyls: &Vec<MainCylinder>, _tors: &Vec<(BendToro)>, table: &Table, scale: f64) -> Vec<LRACLR> {
+fn split_chains(cyls: &Vec<&MainCylinder>, tors: &Vec<&BendToro>) -> Vec<(Vec<MainCylinder>, Vec<BendToro>)> {
+    let mut ret: Vec<(Vec<MainCylinder>, Vec<BendToro>)> = vec![];
+    let mut used_cyls: HashSet<usize> = HashSet::new();
+    let mut used_tors: HashSet<usize> = HashSet::new();
+    let is_connected = |c: &MainCylinder, t: &BendToro| -> bool {
+        c.ca.loc.distance(t.ca.loc) < TOLE || c.ca.loc.distance(t.cb.loc) < TOLE
+            || c.cb.loc.distance(t.ca.loc) < TOLE || c.cb.loc.distance(t.cb.loc) < TOLE
+    };
+    for start in 0..cyls.len() {
+        if (used_cyls.contains(&start)) {
+            continue;
+        }
+        let mut chain_cyls: Vec<usize> = vec![start];
+        let mut chain_tors: Vec<usize> = vec![];
+        used_cyls.insert(start);
+        let mut has_next = true;
+        while (has_next) {
+            has_next = false;
+            for ti in 0..tors.len() {
+                if (!used_tors.contains(&ti) && chain_cyls.iter().any(|ci| is_connected(cyls[*ci], tors[ti]))) {
+                    used_tors.insert(ti);
+                    chain_tors.push(ti);
+                    has_next = true;
+                }
+            }
+            for ci in 0..cyls.len() {
+                if (!used_cyls.contains(&ci) && chain_tors.iter().any(|ti| is_connected(cyls[ci], tors[*ti]))) {
+                    used_cyls.insert(ci);
+                    chain_cyls.push(ci);
+                    has_next = true;
+                }
+            }
+        }
+        ret.push((
+            chain_cyls.iter().map(|i| cyls[*i].clone()).collect(),
+            chain_tors.iter().map(|i| tors[*i].clone()).collect(),
+        ));
+    }
+    ret
+}
+fn is_same_centerline(a: &Vec<MainCylinder>, b: &Vec<MainCylinder>) -> bool {
+    a.iter().all(|ca| {
+        b.iter().any(|cb| {
+            let dir = cb.get_dir();
+            project_point_to_vec(&dir, &cb.ca.loc, &ca.ca.loc).distance(ca.ca.loc) < TOLE
+                && project_point_to_vec(&dir, &cb.ca.loc, &ca.cb.loc).distance(ca.cb.loc) < TOLE
+        })
+    })
+}
+fn find_next_tor(_cyls: &Vec<MainCylinder>, _tors: &Vec<(BendToro)>, table: &Table, scale: f64) -> Result<Vec<LRACLR>, StepAnalysisError> {
     let mut lracmds: Vec<LRACLR> = vec![];
     let mut cc = _cyls.clone();
     let mut tors_bank: HashMap<u64, BendToro> = HashMap::new();
@@ -2845,7 +3236,7 @@ fn find_next_tor(_cyls: &Vec<MainCylinder>, _tors: &Vec<(BendToro)>, table: &Tab
         }
     });
     if (ends.len() == 2) {
-        let (s,e)=calculate_extra_len(ends[0].clone(),ends[1].clone(),table,scale,&tors_bank);
+        let (s,e)=calculate_extra_len(ends[0].clone(),ends[1].clone(),table,scale,&tors_bank)?;
         let mut start: MainCylinder =s.clone();//ends[0].clone();
         let mut tor_id: u64 = { if (start.ca_tor != u64::MAX) { start.ca_tor } else { start.cb_tor } };
         let end: MainCylinder = e.clone();//ends[0].clone();
@@ -2937,8 +3328,9 @@ fn find_next_tor(_cyls: &Vec<MainCylinder>, _tors: &Vec<(BendToro)>, table: &Tab
         //warn!("lra {:?}", lracmds);
     } else {
         warn!("ends_not_found {:?}", ends.len());
+        return Err(StepAnalysisError::PipeEndsNotFound(ends.len()));
     }
-    lracmds
+    Ok(lracmds)
 }
 fn gen_plane_points(s: &MainCylinder, b: &BendToro) -> (Point3, Point3, Point3,) {
     if (s.ca.loc.distance(b.ca.loc) < TOLE) {
@@ -2951,7 +3343,7 @@ fn gen_plane_points(s: &MainCylinder, b: &BendToro) -> (Point3, Point3, Point3,)
         (s.ca.loc.clone(), s.cb.loc.clone(), b.cb.loc.clone())
     }
 }
-fn calculate_extra_len(a:MainCylinder, b:MainCylinder, table: &Table, scale: f64, tors_bank: &HashMap<u64, BendToro>) -> (MainCylinder, MainCylinder) {
+fn calculate_extra_len(a:MainCylinder, b:MainCylinder, table: &Table, scale: f64, tors_bank: &HashMap<u64, BendToro>) -> Result<(MainCylinder, MainCylinder), StepAnalysisError> {
     let mut extra_len_pts: Vec<Point3> = vec![];
     table.shell.iter().for_each(|(k, v)| {
         v.cfs_faces.iter().for_each(|face_holder| {
@@ -2959,7 +3351,7 @@ fn calculate_extra_len(a:MainCylinder, b:MainCylinder, table: &Table, scale: f64
                 PlaceHolder::Ref(name) => {
                     let id = name_to_id(name.clone());
                     match table.face_surface.get(&id) {
-                        None => { println!("NOT FOUND") }
+                        None => { warn!("skipped {}", StepAnalysisError::MissingEntity { entity: "FACE_SURFACE", id: id }) }
                         Some(face_holder) => {
                             let face_bounds: &Vec<PlaceHolder<FaceBoundHolder>> = &face_holder.bounds;
                             match &face_holder.face_geometry {
@@ -2990,8 +3382,13 @@ fn calculate_extra_len(a:MainCylinder, b:MainCylinder, table: &Table, scale: f64
                                                                                                             match table.edge_curve.get(&name_to_id(name.clone())) {
                                                                                                                 None => {}
                                                                                                                 Some(c) => {
-                                                                                                                    let sp = extract_vertex(&table, &c.edge_start, scale).unwrap();
-                                                                                                                    let ep = extract_vertex(&table, &c.edge_end, scale).unwrap();
+                                                                                                                    let (sp, ep) = match (extract_vertex(&table, &c.edge_start, scale), extract_vertex(&table, &c.edge_end, scale)) {
+                                                                                                                        (Some(sp), Some(ep)) => (sp, ep),
+                                                                                                                        _ => {
+                                                                                                                            warn!("skipped {}", StepAnalysisError::MissingEntity { entity: "VERTEX_POINT", id: name_to_id(name.clone()) });
+                                                                                                                            return;
+                                                                                                                        }
+                                                                                                                    };
                                                                                                                     extra_len_pts.push(sp);
                                                                                                                     extra_len_pts.push(ep);
                                                                                                                 }
@@ -3033,18 +3430,18 @@ fn calculate_extra_len(a:MainCylinder, b:MainCylinder, table: &Table, scale: f64
 
     let start_tor={
         if(start.ca_tor!=u64::MAX){
-            tors_bank.get(&start.ca_tor).unwrap()
+            tors_bank.get(&start.ca_tor)
         }else{
-            tors_bank.get(&start.cb_tor).unwrap()
+            tors_bank.get(&start.cb_tor)
         }
-    };
+    }.ok_or(StepAnalysisError::BrokenChain)?;
     let end_tor={
         if(end.ca_tor!=u64::MAX){
-            tors_bank.get(&end.ca_tor).unwrap()
+            tors_bank.get(&end.ca_tor)
         }else{
-            tors_bank.get(&end.cb_tor).unwrap()
+            tors_bank.get(&end.cb_tor)
         }
-    };
+    }.ok_or(StepAnalysisError::BrokenChain)?;
 
     {
         if(start.ca.loc.distance(start_tor.ca.loc) < TOLE || start.ca.loc.distance(start_tor.cb.loc) < TOLE){
@@ -3164,6 +3561,24 @@ fn calculate_extra_len(a:MainCylinder, b:MainCylinder, table: &Table, scale: f64
         }
     }
 
-    (start,end)
+    Ok((start,end))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hollow_tube_wall() {
+        //Demo part: outer chain R 16.85, inner chain R 14.85 on the same centerline, 8 straights and 7 bends
+        let stp = include_bytes!("../files/2.stp").to_vec();
+        let bodies = analyze_stp_bodies(&stp).unwrap();
+        let (body, _) = select_main_body(&bodies).unwrap();
+        assert!((body.outer_radius - 16.85).abs() < 1e-3);
+        assert!((body.inner_radius.unwrap() - 14.85).abs() < 1e-3);
+        assert!((body.wall_thickness().unwrap() - 2.0).abs() < 1e-3);
+        assert_eq!(body.bend_count(), 7);
+        //The inner chain is not reported as a body of its own
+        assert!(bodies.iter().all(|b| (b.outer_radius - 14.85).abs() > 1e-3));
+    }
+}
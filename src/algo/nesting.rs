@@ -0,0 +1,191 @@
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::algo::cnc::{ImportError, LRACLR};
+use crate::algo::compensation::{stock_length, CutAllowances, ElongationTable};
+
+//Lengths in mm
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NestPart {
+    pub name: String,
+    pub cut_length: f64,
+    pub quantity: usize,
+}
+impl NestPart {
+    pub fn from_program(name: &str, program: &Vec<LRACLR>, quantity: usize, table: &ElongationTable, material: &str, allowances: &CutAllowances) -> Self {
+        Self {
+            name: name.to_string(),
+            cut_length: stock_length(program, table, material, allowances),
+            quantity: quantity,
+        }
+    }
+}
+
+//None quantity is an unlimited supply
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StockBar {
+    pub length: f64,
+    pub quantity: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NestingSetup {
+    pub parts: Vec<NestPart>,
+    pub bars: Vec<StockBar>,
+    pub kerf: f64,
+}
+impl Default for NestingSetup {
+    fn default() -> Self {
+        Self {
+            parts: vec![],
+            bars: vec![StockBar { length: 6000.0, quantity: None }],
+            kerf: 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarCut {
+    pub part: String,
+    pub length: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarPlan {
+    pub stock_length: f64,
+    pub cuts: Vec<BarCut>,
+    //Parts and kerfs
+    pub used: f64,
+}
+impl BarPlan {
+    pub fn scrap(&self) -> f64 {
+        self.stock_length - self.used
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NestPlan {
+    pub bars: Vec<BarPlan>,
+    //Parts longer than every bar or left over when the stock ran out
+    pub unplaced: Vec<BarCut>,
+}
+impl NestPlan {
+    pub fn total_stock(&self) -> f64 {
+        self.bars.iter().map(|b| b.stock_length).sum()
+    }
+    pub fn total_scrap(&self) -> f64 {
+        self.bars.iter().map(|b| b.scrap()).sum()
+    }
+}
+
+fn bars_left(setup: &NestingSetup, bars: &Vec<BarPlan>, length: f64) -> bool {
+    match setup.bars.iter().find(|b| b.length == length).and_then(|b| b.quantity) {
+        None => true,
+        Some(q) => bars.iter().filter(|b| b.stock_length == length).count() < q,
+    }
+}
+
+//Best fit decreasing: every part goes to the open bar it fills best, a new bar is the longest one in stock.
+//After packing each bar is swapped for the shortest stock length its cuts fit in
+pub fn nest_parts(setup: &NestingSetup) -> NestPlan {
+    let kerf = setup.kerf.max(0.0);
+    let mut pieces: Vec<BarCut> = vec![];
+    setup.parts.iter().for_each(|p| {
+        (0..p.quantity).for_each(|_| pieces.push(BarCut { part: p.name.clone(), length: p.cut_length }));
+    });
+    pieces.sort_by(|a, b| b.length.total_cmp(&a.length));
+
+    let mut stock: Vec<f64> = setup.bars.iter().map(|b| b.length).filter(|l| *l > 0.0).collect();
+    stock.sort_by(|a, b| b.total_cmp(a));
+
+    let mut plan = NestPlan::default();
+    pieces.into_iter().for_each(|piece| {
+        let need = piece.length + kerf;
+        let best = plan.bars.iter_mut()
+            .filter(|b| b.scrap() >= need)
+            .min_by(|a, b| a.scrap().total_cmp(&b.scrap()));
+        match best {
+            Some(bar) => {
+                bar.used = bar.used + need;
+                bar.cuts.push(piece);
+            }
+            None => {
+                let new_len = stock.iter().find(|l| **l >= need && bars_left(setup, &plan.bars, **l)).cloned();
+                match new_len {
+                    Some(l) => plan.bars.push(BarPlan { stock_length: l, used: need, cuts: vec![piece] }),
+                    None => plan.unplaced.push(piece),
+                }
+            }
+        }
+    });
+
+    let mut shortened: Vec<BarPlan> = vec![];
+    plan.bars.iter().for_each(|bar| {
+        let mut b = bar.clone();
+        let mut others = shortened.clone();
+        others.extend(plan.bars.iter().skip(shortened.len() + 1).cloned());
+        if let Some(l) = stock.iter().rev().find(|l| **l >= bar.used && **l < bar.stock_length && bars_left(setup, &others, **l)) {
+            b.stock_length = *l;
+        }
+        shortened.push(b);
+    });
+    plan.bars = shortened;
+    plan
+}
+
+pub fn nest_plan_to_csv(plan: &NestPlan) -> String {
+    let mut s_out = String::new();
+    s_out.push_str("BAR;STOCK;PART;LENGTH;SCRAP\r\n");
+    plan.bars.iter().enumerate().for_each(|(i, bar)| {
+        bar.cuts.iter().for_each(|cut| {
+            s_out.push_str(format!("{};{:.1};{};{:.1};\r\n", i + 1, bar.stock_length, cut.part, cut.length).as_str());
+        });
+        s_out.push_str(format!("{};{:.1};;;{:.1}\r\n", i + 1, bar.stock_length, bar.scrap()).as_str());
+    });
+    plan.unplaced.iter().for_each(|cut| {
+        s_out.push_str(format!(";;{};{:.1};UNPLACED\r\n", cut.part, cut.length).as_str());
+    });
+    s_out.push_str(format!("TOTAL;{:.1};;;{:.1}\r\n", plan.total_stock(), plan.total_scrap()).as_str());
+    s_out
+}
+
+pub fn save_nest_plan(plan: &NestPlan, path: &PathBuf) -> Result<(), ImportError> {
+    fs::write(path, nest_plan_to_csv(plan)).map_err(|e| ImportError::Io(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(name: &str, cut_length: f64, quantity: usize) -> NestPart {
+        NestPart { name: name.to_string(), cut_length, quantity }
+    }
+
+    #[test]
+    fn best_fit_and_shortest_bar() {
+        let setup = NestingSetup {
+            parts: vec![part("A", 2500.0, 2), part("B", 1000.0, 3), part("C", 4000.0, 1)],
+            bars: vec![StockBar { length: 3000.0, quantity: None }, StockBar { length: 6000.0, quantity: None }],
+            kerf: 3.0,
+        };
+        let plan = nest_parts(&setup);
+        assert!(plan.unplaced.is_empty());
+        let bars: Vec<(f64, Vec<&str>)> = plan.bars.iter().map(|b| (b.stock_length, b.cuts.iter().map(|c| c.part.as_str()).collect())).collect();
+        assert_eq!(bars, vec![(6000.0, vec!["C", "B"]), (6000.0, vec!["A", "A"]), (3000.0, vec!["B", "B"])]);
+        assert_eq!(plan.total_stock(), 15000.0);
+        assert!((plan.total_scrap() - 2982.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn out_of_stock_and_too_long() {
+        let setup = NestingSetup {
+            parts: vec![part("A", 4000.0, 2), part("L", 7000.0, 1)],
+            bars: vec![StockBar { length: 6000.0, quantity: Some(1) }],
+            kerf: 0.0,
+        };
+        let plan = nest_parts(&setup);
+        assert_eq!(plan.bars.len(), 1);
+        let unplaced: Vec<&str> = plan.unplaced.iter().map(|c| c.part.as_str()).collect();
+        assert_eq!(unplaced, vec!["L", "A"]);
+    }
+}
@@ -0,0 +1,304 @@
+use cgmath::InnerSpace;
+use glam::{Mat3, Mat4, Vec3};
+use truck_base::cgmath64::{Point3, Vector3};
+use crate::algo::cnc::{cnc_to_poly, tot_pipe_len, LRACLR};
+
+//Pipe position and frame at one arc-length, x forward, z against the bend plane normal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathSample {
+    pub pt: Point3,
+    pub x_dir: Vector3,
+    pub y_dir: Vector3,
+    pub z_dir: Vector3,
+    pub rot_deg: f64,
+    pub id: u64,
+    //Bend center while on a bend
+    pub cp: Option<Point3>,
+    pub theta: f64,
+    pub bend_radius: f64,
+}
+
+impl PathSample {
+    //Places the sample at the machine origin with the pipe running along -X, rot_step turns the pipe about X in degrees
+    pub fn machine_matrix(&self, rot_step: f64) -> Mat4 {
+        let x_rotation = Mat3::from_cols(
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, rot_step.to_radians().cos() as f32, -rot_step.to_radians().sin() as f32),
+            Vec3::new(0.0, rot_step.to_radians().sin() as f32, rot_step.to_radians().cos() as f32),
+        );
+        let dest_pos = -Vec3::new(self.pt.x as f32, self.pt.y as f32, self.pt.z as f32);
+        let source_x = Vec3::new(self.x_dir.x as f32, self.x_dir.y as f32, self.x_dir.z as f32);
+        let source_y = Vec3::new(self.y_dir.x as f32, self.y_dir.y as f32, self.y_dir.z as f32);
+        let source_z = Vec3::new(self.z_dir.x as f32, self.z_dir.y as f32, self.z_dir.z as f32);
+        let m_source = Mat3::from_cols(source_x, source_y, source_z);
+        let m_dest = Mat3::from_cols(Vec3::X, Vec3::Y, Vec3::Z);
+        let z_mirror = Mat3::from_cols(
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        );
+        let x_mirror = Mat3::from_cols(
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        );
+        let rot_matrix: Mat3 = x_rotation * x_mirror * z_mirror * m_dest * m_source.transpose();
+        Mat4::from_mat3(rot_matrix) * Mat4::from_translation(dest_pos)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PathPiece {
+    Straight {
+        id: u64,
+        loc: Point3,
+        dir: Vector3,
+        rot_deg: f64,
+        plane_norm: Option<Vector3>,
+        //Radius of the bend before the straight, 0 in front of the first bend
+        prev_bend_radius: f64,
+    },
+    Arc {
+        id: u64,
+        center: Point3,
+        v1: Vector3,
+        axis: Vector3,
+        plane_norm: Vector3,
+        bend_radius: f64,
+    },
+}
+
+//Centerline of the program built once, samples are found by binary search over the piece end lengths
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipePath {
+    program: Vec<LRACLR>,
+    up_dir: Vector3,
+    len: f64,
+    starts: Vec<f64>,
+    ends: Vec<f64>,
+    pieces: Vec<PathPiece>,
+    last_bend_radius: f64,
+}
+impl Default for PipePath {
+    fn default() -> Self {
+        PipePath::new(&vec![], &Vector3::new(0.0, 0.0, 1.0))
+    }
+}
+impl PipePath {
+    pub fn new(cmnd: &Vec<LRACLR>, up_dir: &Vector3) -> Self {
+        //cnc_to_poly gives one straight per row and one arc per bending row, both in program order
+        let (circles, tors) = cnc_to_poly(cmnd, up_dir);
+        let mut starts: Vec<f64> = vec![];
+        let mut ends: Vec<f64> = vec![];
+        let mut pieces: Vec<PathPiece> = vec![];
+        let mut fragment_len = 0.0;
+        let mut bend_radius = 0.0;
+        let mut tor_indx = 0;
+        let mut prev_plane_norm: Option<Vector3> = None;
+
+        for (lracl, c) in cmnd.iter().zip(circles.iter()) {
+            let next_arc = if (lracl.clr > 0.0 && tor_indx < tors.len()) {
+                tor_indx = tor_indx + 1;
+                Some(&tors[tor_indx - 1])
+            } else {
+                None
+            };
+            let plane_norm = match next_arc {
+                Some(arc) => Some(arc.bend_plane_norm.normalize()),
+                None => prev_plane_norm,
+            };
+            starts.push(fragment_len);
+            fragment_len += c.h;
+            ends.push(fragment_len);
+            pieces.push(PathPiece::Straight {
+                id: c.id,
+                loc: c.ca.loc,
+                dir: c.ca.dir,
+                rot_deg: lracl.r,
+                plane_norm: plane_norm,
+                prev_bend_radius: bend_radius,
+            });
+
+            prev_plane_norm = None;
+            if let Some(arc) = next_arc {
+                bend_radius = arc.bend_radius;
+                prev_plane_norm = Some(arc.bend_plane_norm.normalize());
+                let v1: Vector3 = arc.ca.loc - arc.bend_center_point;
+                let v2: Vector3 = arc.cb.loc - arc.bend_center_point;
+                starts.push(fragment_len);
+                fragment_len += arc.angle().0 * arc.bend_radius;
+                ends.push(fragment_len);
+                pieces.push(PathPiece::Arc {
+                    id: arc.id,
+                    center: arc.bend_center_point,
+                    v1: v1,
+                    axis: v1.cross(v2).normalize(),
+                    plane_norm: arc.bend_plane_norm.normalize(),
+                    bend_radius: arc.bend_radius,
+                });
+            }
+        }
+
+        Self {
+            program: cmnd.clone(),
+            up_dir: up_dir.clone(),
+            len: tot_pipe_len(cmnd),
+            starts: starts,
+            ends: ends,
+            pieces: pieces,
+            last_bend_radius: bend_radius,
+        }
+    }
+    //True if the path was built from this program and up direction
+    pub fn is_for(&self, cmnd: &Vec<LRACLR>, up_dir: &Vector3) -> bool {
+        self.up_dir == *up_dir && self.program == *cmnd
+    }
+    pub fn len(&self) -> f64 {
+        self.len
+    }
+    //Piece id with its start and end length
+    pub fn spans(&self) -> Vec<(u64, f64, f64)> {
+        self.pieces.iter().enumerate().map(|(i, piece)| {
+            let id = match piece {
+                PathPiece::Straight { id, .. } => *id,
+                PathPiece::Arc { id, .. } => *id,
+            };
+            (id, self.starts[i], self.ends[i])
+        }).collect()
+    }
+    pub fn at(&self, t: f64) -> PathSample {
+        self.at_dist(self.len * t)
+    }
+    //Outside the path the sample keeps the default frame at the origin
+    pub fn at_dist(&self, dist: f64) -> PathSample {
+        let mut sample = PathSample {
+            pt: Point3::new(0.0, 0.0, 0.0),
+            x_dir: Vector3::new(1.0, 0.0, 0.0),
+            y_dir: Vector3::new(0.0, 1.0, 0.0),
+            z_dir: Vector3::new(0.0, 0.0, 1.0),
+            rot_deg: 0.0,
+            id: 0,
+            cp: None,
+            theta: 0.0,
+            bend_radius: self.last_bend_radius,
+        };
+        let idx = self.ends.partition_point(|e| *e <= dist);
+        if (idx == self.pieces.len() || dist < self.starts[idx]) {
+            return sample;
+        }
+        let offset = dist - self.starts[idx];
+        match &self.pieces[idx] {
+            PathPiece::Straight { id, loc, dir, rot_deg, plane_norm, prev_bend_radius } => {
+                sample.id = *id;
+                sample.rot_deg = *rot_deg;
+                sample.bend_radius = *prev_bend_radius;
+                sample.pt = *loc + *dir * offset;
+                sample.x_dir = *dir;
+                if let Some(norm) = plane_norm {
+                    sample.z_dir = -*norm;
+                    sample.y_dir = sample.z_dir.cross(sample.x_dir);
+                }
+            }
+            PathPiece::Arc { id, center, v1, axis, plane_norm, bend_radius } => {
+                let theta = offset / bend_radius;
+                let v_target = *v1 * theta.cos() + axis.cross(*v1) * theta.sin();
+                sample.id = *id;
+                sample.cp = Some(*center);
+                sample.theta = theta;
+                sample.bend_radius = *bend_radius;
+                sample.pt = *center + v_target;
+                sample.z_dir = -*plane_norm;
+                sample.y_dir = v_target.normalize();
+                sample.x_dir = sample.y_dir.cross(sample.z_dir);
+            }
+        }
+        sample
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::cnc::test_row;
+
+    //The linear search PipePath replaced, kept to check the evaluator against it
+    fn reference_byt(t: f64, cmnd: &Vec<LRACLR>, up_dir: &Vector3) -> (Point3, Vector3, Vector3, Vector3, f64, u64, Option<Point3>, f64, f64) {
+        let dist = tot_pipe_len(cmnd) * t;
+        let (circles, tors) = cnc_to_poly(cmnd, up_dir);
+        let indexes = circles.len() + tors.len();
+        let mut x_dir = Vector3::new(1.0, 0.0, 0.0);
+        let mut y_dir = Vector3::new(0.0, 1.0, 0.0);
+        let mut z_dir = Vector3::new(0.0, 0.0, 1.0);
+        let mut pt: Point3 = Point3::new(0.0, 0.0, 0.0);
+        let mut rot_deg = 0.0;
+        let mut cp: Option<Point3> = None;
+        let mut theta: f64 = 0.0;
+        let mut fragment_len = 0.0;
+        let mut id: u64 = 0;
+        let mut bend_radius = 0.0;
+        for i in (0..indexes).step_by(2) {
+            if let Some(c) = circles.iter().find(|cil| cil.id == i as u64) {
+                let min = fragment_len;
+                fragment_len += c.h;
+                if (dist >= min && dist < fragment_len) {
+                    id = c.id;
+                    rot_deg = LRACLR::rotate_by_id(c.id as i32, cmnd);
+                    pt = c.ca.loc + c.ca.dir * (dist - min);
+                    x_dir = c.ca.dir;
+                    if let Some(arc) = tors.iter().find(|tor| tor.id == (i + 1) as u64) {
+                        z_dir = -arc.bend_plane_norm.normalize();
+                        y_dir = z_dir.cross(x_dir);
+                    } else if let Some(arc) = tors.iter().find(|tor| tor.id == (i.saturating_sub(1)) as u64) {
+                        z_dir = -arc.bend_plane_norm.normalize();
+                        y_dir = z_dir.cross(x_dir);
+                    }
+                    break;
+                } else if let Some(arc) = tors.iter().find(|tor| tor.id == (i + 1) as u64) {
+                    bend_radius = arc.bend_radius;
+                    let min = fragment_len;
+                    fragment_len += arc.angle().0 * arc.bend_radius;
+                    if (dist >= min && dist < fragment_len) {
+                        id = arc.id;
+                        cp = Some(arc.bend_center_point);
+                        theta = (dist - min) / arc.bend_radius;
+                        let v1: Vector3 = arc.ca.loc - arc.bend_center_point;
+                        let v2: Vector3 = arc.cb.loc - arc.bend_center_point;
+                        let axis = v1.cross(v2).normalize();
+                        let v_target = v1 * theta.cos() + axis.cross(v1) * theta.sin();
+                        pt = arc.bend_center_point + v_target;
+                        z_dir = -arc.bend_plane_norm.normalize();
+                        y_dir = v_target.normalize();
+                        x_dir = y_dir.cross(z_dir);
+                        break;
+                    }
+                }
+            }
+        }
+        (pt, x_dir, y_dir, z_dir, rot_deg, id, cp, theta, bend_radius)
+    }
+
+    #[test]
+    fn at_dist_matches_linear_search() {
+        let program = vec![
+            test_row(0, 100.0, 0.0, 90.0, 60.0),
+            test_row(1, 50.0, 45.0, 30.0, 80.0),
+            test_row(2, 120.0, -90.0, 120.0, 60.0),
+            test_row(3, 200.0, 0.0, 0.0, 0.0),
+        ];
+        let up_dir = Vector3::new(0.0, 0.0, 1.0);
+        let path = PipePath::new(&program, &up_dir);
+        let close = |a: Vector3, b: Vector3| (a - b).magnitude() < 1e-9;
+        (0..=1000).for_each(|i| {
+            let t = i as f64 / 1000.0;
+            let s = path.at(t);
+            let (pt, x_dir, y_dir, z_dir, rot_deg, id, cp, theta, bend_radius) = reference_byt(t, &program, &up_dir);
+            assert_eq!(s.id, id, "t {}", t);
+            assert!(close(s.pt - Point3::new(0.0, 0.0, 0.0), pt - Point3::new(0.0, 0.0, 0.0)), "t {}", t);
+            assert!(close(s.x_dir, x_dir) && close(s.y_dir, y_dir) && close(s.z_dir, z_dir), "t {}", t);
+            assert_eq!(s.rot_deg, rot_deg, "t {}", t);
+            assert_eq!(s.cp.is_some(), cp.is_some(), "t {}", t);
+            assert!((s.theta - theta).abs() < 1e-12, "t {}", t);
+            assert_eq!(s.bend_radius, bend_radius, "t {}", t);
+        });
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs;
+use std::path::PathBuf;
+use crate::algo::cnc::{lraclr_to_csv, BendLoad, ImportError, LRACLR};
+use crate::algo::deformation::BendDeformation;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobInfo {
+    pub name: String,
+    pub material: String,
+    pub diameter: f64,
+    pub thickness: f64,
+    pub cut_length: f64,
+    //Predicted deformation of the bend rows
+    pub bends: Vec<BendDeformation>,
+    pub loads: Vec<BendLoad>,
+}
+impl JobInfo {
+    pub fn bend(&self, row: usize) -> Option<&BendDeformation> {
+        self.bends.iter().find(|b| b.row == row)
+    }
+    pub fn load(&self, row: usize) -> Option<&BendLoad> {
+        self.loads.iter().find(|b| b.row == row)
+    }
+}
+
+pub trait PostProcessor {
+    fn name(&self) -> String;
+    fn extension(&self) -> String;
+    fn generate(&self, program: &Vec<LRACLR>, job: &JobInfo) -> String;
+}
+
+//The idx;L;R;A;CLR;0 table of save_csv, optionally after a CUT;len line for the saw
+pub struct CsvPost {
+    pub cut_length: bool,
+}
+impl PostProcessor for CsvPost {
+    fn name(&self) -> String {
+        if (self.cut_length) { "CSV with cut length".to_string() } else { "CSV".to_string() }
+    }
+    fn extension(&self) -> String {
+        "csv".to_string()
+    }
+    fn generate(&self, program: &Vec<LRACLR>, job: &JobInfo) -> String {
+        lraclr_to_csv(program, if (self.cut_length) { Some(job.cut_length) } else { None })
+    }
+}
+
+//Generic Y (feed) B (rotation) C (bend) text program
+pub struct YbcPost;
+impl PostProcessor for YbcPost {
+    fn name(&self) -> String {
+        "YBC".to_string()
+    }
+    fn extension(&self) -> String {
+        "ybc".to_string()
+    }
+    fn generate(&self, program: &Vec<LRACLR>, job: &JobInfo) -> String {
+        let mut s_out = String::new();
+        s_out.push_str(format!("; JOB {}\r\n", job.name).as_str());
+        s_out.push_str(format!("; MATERIAL {} D {:.3} S {:.3}\r\n", job.material, job.diameter, job.thickness).as_str());
+        s_out.push_str(format!("; CUT {:.3}\r\n", job.cut_length).as_str());
+        program.iter().enumerate().for_each(|(i, lracl)| {
+            if (lracl.clr > 0.0) {
+                s_out.push_str(format!("N{} Y{:.3} B{:.3} C{:.3} R{:.3}\r\n", i + 1, lracl.l, lracl.r, lracl.a, lracl.clr).as_str());
+            } else {
+                s_out.push_str(format!("N{} Y{:.3}\r\n", i + 1, lracl.l).as_str());
+            }
+        });
+        s_out.push_str("M30\r\n");
+        s_out
+    }
+}
+
+//Per row report for the quality check: program, wall thinning, thickening and ovality in %, loads in kNm and kN
+pub struct ReportPost;
+impl PostProcessor for ReportPost {
+    fn name(&self) -> String {
+        "Report".to_string()
+    }
+    fn extension(&self) -> String {
+        "csv".to_string()
+    }
+    fn generate(&self, program: &Vec<LRACLR>, job: &JobInfo) -> String {
+        let mut s_out = String::new();
+        s_out.push_str("N;L;R;A;CLR;OUTER_WALL;INNER_WALL;THINNING;THICKENING;OVALITY;FLAGS;MOMENT;CLAMP_FORCE;PRESSURE_DIE_FORCE;LOAD_FLAGS\r\n");
+        program.iter().enumerate().for_each(|(i, lracl)| {
+            s_out.push_str(format!("{};{:.3};{:.3};{:.3};{:.3}", i + 1, lracl.l, lracl.r, lracl.a, lracl.clr).as_str());
+            match job.bend(i) {
+                None => s_out.push_str(";;;;;;"),
+                Some(b) => {
+                    let flags = b.flags.iter().map(|f| format!("{:?}", f)).collect::<Vec<String>>().join(" ");
+                    s_out.push_str(format!(";{:.3};{:.3};{:.2};{:.2};{:.2};{}", b.outer_wall, b.inner_wall, b.thinning, b.thickening, b.ovality, flags).as_str());
+                }
+            }
+            match job.load(i) {
+                None => s_out.push_str(";;;;"),
+                Some(b) => {
+                    let flags = b.flags.iter().map(|f| format!("{:?}", f)).collect::<Vec<String>>().join(" ");
+                    s_out.push_str(format!(";{:.3};{:.2};{:.2};{}", b.moment, b.clamp_force, b.pressure_die_force, flags).as_str());
+                }
+            }
+            s_out.push_str("\r\n");
+        });
+        s_out
+    }
+}
+
+//Text template with [header], [line] and [footer] sections.
+//Job placeholders: {name} {material} {diameter} {thickness} {cut_length} {rows}
+//Line placeholders: {n} {l} {r} {a} {clr} {thin} {thick} {oval}
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplatePost {
+    pub name: String,
+    pub extension: String,
+    pub header: String,
+    pub line: String,
+    pub footer: String,
+}
+impl TemplatePost {
+    pub fn parse(name: &str, extension: &str, text: &str) -> Result<Self, ImportError> {
+        let mut header = String::new();
+        let mut line = String::new();
+        let mut footer = String::new();
+        let mut section: Option<&str> = None;
+        text.lines().for_each(|l| {
+            match l.trim() {
+                "[header]" => section = Some("header"),
+                "[line]" => section = Some("line"),
+                "[footer]" => section = Some("footer"),
+                _ => {
+                    let dst = match section {
+                        Some("header") => Some(&mut header),
+                        Some("line") => Some(&mut line),
+                        Some("footer") => Some(&mut footer),
+                        _ => None,
+                    };
+                    match dst {
+                        None => {}
+                        Some(dst) => {
+                            dst.push_str(l);
+                            dst.push_str("\r\n");
+                        }
+                    }
+                }
+            }
+        });
+        if (line.is_empty()) {
+            return Err(ImportError::Format("template has no [line] section".to_string()));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            extension: extension.to_string(),
+            header: header,
+            line: line,
+            footer: footer,
+        })
+    }
+    fn fill_job(&self, text: &str, program: &Vec<LRACLR>, job: &JobInfo) -> String {
+        text.replace("{name}", job.name.as_str())
+            .replace("{material}", job.material.as_str())
+            .replace("{diameter}", format!("{:.3}", job.diameter).as_str())
+            .replace("{thickness}", format!("{:.3}", job.thickness).as_str())
+            .replace("{cut_length}", format!("{:.3}", job.cut_length).as_str())
+            .replace("{rows}", program.len().to_string().as_str())
+    }
+}
+impl PostProcessor for TemplatePost {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn extension(&self) -> String {
+        self.extension.clone()
+    }
+    fn generate(&self, program: &Vec<LRACLR>, job: &JobInfo) -> String {
+        let mut s_out = self.fill_job(&self.header, program, job);
+        program.iter().enumerate().for_each(|(i, lracl)| {
+            let row = self.line
+                .replace("{n}", (i + 1).to_string().as_str())
+                .replace("{l}", format!("{:.3}", lracl.l).as_str())
+                .replace("{r}", format!("{:.3}", lracl.r).as_str())
+                .replace("{a}", format!("{:.3}", lracl.a).as_str())
+                .replace("{clr}", format!("{:.3}", lracl.clr).as_str());
+            let row = match job.bend(i) {
+                None => row.replace("{thin}", "").replace("{thick}", "").replace("{oval}", ""),
+                Some(b) => row
+                    .replace("{thin}", format!("{:.2}", b.thinning).as_str())
+                    .replace("{thick}", format!("{:.2}", b.thickening).as_str())
+                    .replace("{oval}", format!("{:.2}", b.ovality).as_str()),
+            };
+            s_out.push_str(row.as_str());
+        });
+        s_out.push_str(self.fill_job(&self.footer, program, job).as_str());
+        s_out
+    }
+}
+
+pub fn load_template(path: &PathBuf) -> Result<TemplatePost, ImportError> {
+    let text = fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))?;
+    let name = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or("Template".to_string());
+    TemplatePost::parse(&name, "txt", &text)
+}
+
+pub fn builtin_posts() -> Vec<Box<dyn PostProcessor>> {
+    vec![Box::new(CsvPost { cut_length: false }), Box::new(CsvPost { cut_length: true }), Box::new(YbcPost), Box::new(ReportPost)]
+}
+
+pub fn export_program(post: &dyn PostProcessor, program: &Vec<LRACLR>, job: &JobInfo, path: &PathBuf) -> Result<(), ImportError> {
+    fs::write(path, post.generate(program, job)).map_err(|e| ImportError::Io(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::cnc::test_row;
+
+    fn job() -> JobInfo {
+        JobInfo { name: "P1".to_string(), material: "S235".to_string(), diameter: 20.0, thickness: 2.0, cut_length: 200.0, bends: vec![], loads: vec![] }
+    }
+    fn program() -> Vec<LRACLR> {
+        vec![test_row(0, 100.0, 10.0, 90.0, 40.0), test_row(1, 50.0, 0.0, 0.0, 0.0)]
+    }
+
+    #[test]
+    fn ybc_output() {
+        let text = YbcPost.generate(&program(), &job());
+        assert_eq!(text, "; JOB P1\r\n; MATERIAL S235 D 20.000 S 2.000\r\n; CUT 200.000\r\nN1 Y100.000 B10.000 C90.000 R40.000\r\nN2 Y50.000\r\nM30\r\n");
+    }
+
+    #[test]
+    fn csv_output() {
+        assert_eq!(CsvPost { cut_length: false }.generate(&program(), &job()), "0;100;10;90;40;0\r\n1;50;\r\n");
+        assert_eq!(CsvPost { cut_length: true }.generate(&program(), &job()), "CUT;200.000\r\n0;100;10;90;40;0\r\n1;50;\r\n");
+    }
+
+    #[test]
+    fn template_output() {
+        let post = TemplatePost::parse("T", "txt", "[header]\nJOB {name} ROWS {rows}\n[line]\n{n};{l};{a};{thin}\n[footer]\nEND {cut_length}\n").unwrap();
+        assert_eq!(post.generate(&program(), &job()), "JOB P1 ROWS 2\r\n1;100.000;90.000;\r\n2;50.000;0.000;\r\nEND 200.000\r\n");
+        assert!(TemplatePost::parse("T", "txt", "[header]\nJOB\n").is_err());
+    }
+}
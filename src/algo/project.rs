@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::algo::cnc::{ImportError, LRACLR};
+use crate::algo::compensation::CutAllowances;
+use crate::algo::deformation::DeformationLimits;
+use crate::algo::machine::MachineConfig;
+use crate::algo::tooling::DieStack;
+
+//2: die stacks instead of the three roller CLRs, version 1 files get the stacks from the tooling library on load
+pub const PROJECT_VERSION: u32 = 2;
+
+//Complete job: program, pipe, tooling and the source model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectFile {
+    pub version: u32,
+    pub segments: Vec<LRACLR>,
+    pub diameter: f64,
+    pub thickness: f64,
+    #[serde(default)]
+    pub inner_diameter: Option<f64>,
+    #[serde(default)]
+    pub material: String,
+    #[serde(default)]
+    pub stacks: Vec<DieStack>,
+    #[serde(default)]
+    pub deformation_limits: DeformationLimits,
+    #[serde(default)]
+    pub allowances: CutAllowances,
+    //Written for the shop floor, recalculated on load
+    #[serde(default)]
+    pub cut_length: f64,
+    pub up_dir: [f64; 3],
+    #[serde(default)]
+    pub machine: Option<MachineConfig>,
+    #[serde(default)]
+    pub source_path: Option<String>,
+    //STEP file embedded as base64 so the job can be opened without the original file
+    #[serde(default, with = "step_bytes")]
+    pub source_step: Option<Vec<u8>>,
+}
+
+mod step_bytes {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn encode(bytes: &[u8]) -> String {
+        STANDARD.encode(bytes)
+    }
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            None => s.serialize_none(),
+            Some(b) => s.serialize_some(&encode(b)),
+        }
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+        match Option::<String>::deserialize(d)? {
+            None => Ok(None),
+            Some(text) => STANDARD.decode(text).map(Some).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+pub fn save_project(project: &ProjectFile, path: &PathBuf) -> Result<(), ImportError> {
+    let json = serde_json::to_string_pretty(project).map_err(|e| ImportError::Format(e.to_string()))?;
+    fs::write(path, json).map_err(|e| ImportError::Io(e.to_string()))
+}
+
+pub fn load_project(path: &PathBuf) -> Result<ProjectFile, ImportError> {
+    let text = fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))?;
+    parse_project(&text)
+}
+pub fn parse_project(text: &str) -> Result<ProjectFile, ImportError> {
+    let project: ProjectFile = serde_json::from_str(text).map_err(|e| ImportError::Format(e.to_string()))?;
+    if (project.version > PROJECT_VERSION) {
+        return Err(ImportError::Format(format!("project version {} is newer than supported {}", project.version, PROJECT_VERSION)));
+    }
+    if (project.segments.is_empty()) {
+        return Err(ImportError::NotEnoughPoints(0));
+    }
+    Ok(project)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_bytes_round_trip() {
+        let text = r#"{"version":2,"segments":[{"id1":0,"id2":1,"l":100.0,"r":0.0,"a":0.0,"clr":0.0,"pipe_radius":10.0}],
+            "diameter":20.0,"thickness":2.0,"up_dir":[0.0,0.0,1.0]}"#;
+        let mut project = parse_project(text).unwrap();
+        //cp1251 bytes are not valid UTF-8
+        project.source_step = Some(vec![0x49, 0x53, 0xD2, 0xF0, 0xF3, 0xE1, 0xE0]);
+        let json = serde_json::to_string(&project).unwrap();
+        let back = parse_project(&json).unwrap();
+        assert_eq!(back.source_step, project.source_step);
+    }
+
+    #[test]
+    fn newer_version_is_rejected() {
+        let text = r#"{"version":99,"segments":[{"id1":0,"id2":1,"l":100.0,"r":0.0,"a":0.0,"clr":0.0,"pipe_radius":10.0}],
+            "diameter":20.0,"thickness":2.0,"up_dir":[0.0,0.0,1.0]}"#;
+        assert!(parse_project(text).is_err());
+    }
+
+    #[test]
+    fn version_1_rollers_are_ignored() {
+        let text = r#"{"version":1,"segments":[{"id1":0,"id2":1,"l":100.0,"r":0.0,"a":0.0,"clr":0.0,"pipe_radius":10.0}],
+            "diameter":20.0,"thickness":2.0,"roller_a":60.0,"roller_b":60.0,"roller_c":0.0,"up_dir":[0.0,0.0,1.0]}"#;
+        let project = parse_project(text).unwrap();
+        assert!(project.stacks.is_empty());
+    }
+}
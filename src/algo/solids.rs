@@ -1,5 +1,7 @@
 use std::f64::consts::PI;
+#[cfg(feature = "bevy")]
 use bevy::asset::RenderAssetUsages;
+#[cfg(feature = "bevy")]
 use bevy::mesh::{GenerateTangentsError, Indices, Mesh, PrimitiveTopology};
 use cgmath::{EuclideanSpace, InnerSpace, MetricSpace, Rad};
 use truck_base::cgmath64::{Point3, Vector3, Zero};
@@ -9,19 +11,44 @@ use truck_meshalgo::tessellation::{MeshableShape, MeshedShape};
 use truck_modeling::{builder, Shell, Solid, Vertex, Wire};
 use truck_polymesh::PolygonMesh;
 use truck_topology::shell;
+use crate::algo::tooling::DieStack;
 
-pub fn generate_roller(r_bend: f64, d_pipe: f64) -> [Mesh; 4] {
+//Tessellation tolerance of the die solids, mm
+pub const ROLLER_TOLERANCE: f64 = 0.5;
+
+#[cfg(feature = "bevy")]
+pub fn generate_roller(stack: &DieStack, d_pipe: f64) -> [Mesh; 4] {
+    let [shell, shell_a, shell_b, shell_support] = roller_shells(stack, d_pipe);
+    let roller = mesher(&shell);
+    let roller_helper_a = mesher(&shell_a);
+    let roller_helper_b = mesher(&shell_b);
+    let roller_support = mesher(&shell_support);
+
+    [roller, roller_helper_a,roller_helper_b,roller_support]
+}
+
+//Triangles of the bend die, die insert, clamp die and pressure die in the die frame
+pub fn roller_triangles(stack: &DieStack, d_pipe: f64) -> [Vec<[Point3; 3]>; 4] {
+    roller_shells(stack, d_pipe).map(|shell| {
+        let truck_mesh: PolygonMesh = shell.robust_triangulation(ROLLER_TOLERANCE).to_polygon();
+        truck_mesh.tri_faces().into_iter().map(|vtx| {
+            [truck_mesh.positions()[vtx[0].pos], truck_mesh.positions()[vtx[1].pos], truck_mesh.positions()[vtx[2].pos]]
+        }).collect()
+    })
+}
+
+fn roller_shells(stack: &DieStack, d_pipe: f64) -> [Shell; 4] {
 
 
     //let total_l=198.0;
     ///let l=total_l-r_bend;
-    let r_b = r_bend;
+    let r_b = stack.bend_die.clr;
     let r_p = d_pipe / 2.0;
-    let h = 50.0;
+    let h = stack.bend_die.stack_height;
     let c = h / 2.0;
 
     let fixed_len=226.0;
-    let helper_1_thin=28.0;
+    let helper_1_thin=stack.clamp_die.width;
     let helper_2_thin=fixed_len-helper_1_thin-r_b;
 
 
@@ -99,7 +126,7 @@ pub fn generate_roller(r_bend: f64, d_pipe: f64) -> [Mesh; 4] {
     ].into();
 
     let cup_a = builder::try_attach_plane(&[wire_a.inverse()]).unwrap();
-    let solid_a = builder::tsweep(&cup_a, Vector3::new(60.0, 0.0, 0.0));
+    let solid_a = builder::tsweep(&cup_a, Vector3::new(stack.clamp_die.length, 0.0, 0.0));
     let shell_a = solid_a.into_boundaries().pop().unwrap();
 
 
@@ -129,25 +156,21 @@ pub fn generate_roller(r_bend: f64, d_pipe: f64) -> [Mesh; 4] {
     ].into();
 
     let cup_b = builder::try_attach_plane(&[wire_b.inverse()]).unwrap();
-    let solid_b = builder::tsweep(&cup_b, Vector3::new(60.0, 0.0, 0.0));
+    let solid_b = builder::tsweep(&cup_b, Vector3::new(stack.clamp_die.length, 0.0, 0.0));
     let shell_b = solid_b.into_boundaries().pop().unwrap();
 
-    let solid_support = builder::tsweep(&cup_b, Vector3::new(300.0, 0.0, 0.0));
+    let solid_support = builder::tsweep(&cup_b, Vector3::new(stack.pressure_die.length, 0.0, 0.0));
     let shell_support = solid_support.into_boundaries().pop().unwrap();
 
 
-    let roller = mesher(&shell);
-    let roller_helper_a = mesher(&shell_a);
-    let roller_helper_b = mesher(&shell_b);
-    let roller_support = mesher(&shell_support);
-
-    [roller, roller_helper_a,roller_helper_b,roller_support]
+    [shell, shell_a, shell_b, shell_support]
 }
 
 
+#[cfg(feature = "bevy")]
 fn mesher(shell: &Shell) -> Mesh {
 
-    let tolerance = 0.5;
+    let tolerance = ROLLER_TOLERANCE;
 
     let mut truck_mesh: PolygonMesh = shell.robust_triangulation(tolerance).to_polygon();
     //truck_mesh.add_smooth_normals(0.8, true);
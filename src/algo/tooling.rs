@@ -0,0 +1,248 @@
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::algo::cnc::{ImportError, LRACLR};
+use crate::algo::machine::HeadLayout;
+use crate::algo::TOLE;
+
+pub const TOOLING_FILE: &str = "tooling.json";
+//Groove may be this much bigger than the pipe OD
+pub const GROOVE_TOLE: f64 = 1.0;
+pub const STACK_GAP: f64 = 2.0;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BendDie {
+    pub id: String,
+    pub clr: f64,
+    pub groove_d: f64,
+    pub stack_height: f64,
+    //Smallest CLR/D the die can bend without wrinkling
+    #[serde(default = "default_min_clr_d")]
+    pub min_clr_d: f64,
+}
+fn default_min_clr_d() -> f64 {
+    1.0
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClampDie {
+    pub id: String,
+    pub length: f64,
+    pub width: f64,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PressureDie {
+    pub id: String,
+    pub length: f64,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Wiper {
+    pub id: String,
+    pub groove_d: f64,
+    pub clr: f64,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mandrel {
+    pub id: String,
+    pub d: f64,
+    pub balls: u32,
+}
+
+//Tools mounted on one level of the bend head
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DieStack {
+    pub id: String,
+    pub bend_die: BendDie,
+    pub clamp_die: ClampDie,
+    pub pressure_die: PressureDie,
+    #[serde(default)]
+    pub wiper: Option<Wiper>,
+}
+impl DieStack {
+    //Stack with the default dimensions of the machine, used when no library is configured
+    pub fn generic(clr: f64, pipe_d: f64) -> Self {
+        let id = format!("R{:.1}xD{:.1}", clr, pipe_d);
+        Self {
+            id: id.clone(),
+            bend_die: BendDie { id: id.clone(), clr: clr, groove_d: pipe_d, stack_height: 50.0, min_clr_d: default_min_clr_d() },
+            clamp_die: ClampDie { id: id.clone(), length: 60.0, width: 28.0 },
+            pressure_die: PressureDie { id: id, length: 300.0 },
+            wiper: None,
+        }
+    }
+    pub fn fits(&self, clr: f64, pipe_d: f64) -> bool {
+        (self.bend_die.clr - clr).abs() < TOLE && self.bend_die.groove_d >= pipe_d - TOLE && self.bend_die.groove_d - pipe_d < GROOVE_TOLE
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolingError {
+    NoDieStack { row: usize, clr: f64, pipe_d: f64 },
+    UnknownStack { id: String },
+    TooManyStacks { count: usize, max: usize },
+}
+impl std::fmt::Display for ToolingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolingError::NoDieStack { row, clr, pipe_d } => write!(f, "Row {}: no die stack for CLR {:.2} and pipe D {:.2}", row, clr, pipe_d),
+            ToolingError::UnknownStack { id } => write!(f, "Head layout: stack {} is not in the tooling library", id),
+            ToolingError::TooManyStacks { count, max } => write!(f, "Program needs {} die stacks, the head holds {}", count, max),
+        }
+    }
+}
+impl std::error::Error for ToolingError {}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Tooling {
+    pub stacks: Vec<DieStack>,
+    #[serde(default)]
+    pub mandrels: Vec<Mandrel>,
+}
+impl Tooling {
+    pub fn find_stack(&self, clr: f64, pipe_d: f64) -> Option<&DieStack> {
+        self.stacks.iter().find(|s| s.fits(clr, pipe_d))
+    }
+    pub fn find_mandrel(&self, inner_d: f64) -> Option<&Mandrel> {
+        self.mandrels.iter()
+            .filter(|m| m.d < inner_d)
+            .max_by(|a, b| a.d.partial_cmp(&b.d).unwrap())
+    }
+}
+
+pub fn load_tooling(path: &PathBuf) -> Result<Tooling, ImportError> {
+    let text = fs::read_to_string(path).map_err(|e| ImportError::Io(e.to_string()))?;
+    serde_json::from_str(&text).map_err(|e| ImportError::Format(e.to_string()))
+}
+pub fn save_tooling(tooling: &Tooling, path: &PathBuf) -> Result<(), ImportError> {
+    let json = serde_json::to_string_pretty(tooling).map_err(|e| ImportError::Format(e.to_string()))?;
+    fs::write(path, json).map_err(|e| ImportError::Io(e.to_string()))
+}
+
+//Stacks mounted on the head top down. With a head layout the configured stacks are taken as is,
+//otherwise the program gets one stack per CLR in order of first use, generic ones without a library
+pub fn assign_stacks(lraclr_arr: &Vec<LRACLR>, tooling: &Tooling, head: &HeadLayout) -> (Vec<DieStack>, Vec<ToolingError>) {
+    let mut stacks: Vec<DieStack> = vec![];
+    let mut errors: Vec<ToolingError> = vec![];
+    if (!head.stacks.is_empty()) {
+        head.stacks.iter().for_each(|id| {
+            match tooling.stacks.iter().find(|s| s.id == *id) {
+                Some(s) => stacks.push(s.clone()),
+                None => errors.push(ToolingError::UnknownStack { id: id.clone() }),
+            }
+        });
+        lraclr_arr.iter().enumerate().for_each(|(row, lracl)| {
+            let pipe_d = lracl.pipe_radius * 2.0;
+            if (lracl.clr > 0.0 && !stacks.iter().any(|s| s.fits(lracl.clr, pipe_d))) {
+                errors.push(ToolingError::NoDieStack { row: row, clr: lracl.clr, pipe_d: pipe_d });
+            }
+        });
+    } else {
+        lraclr_arr.iter().enumerate().for_each(|(row, lracl)| {
+            if (lracl.clr > 0.0) {
+                let pipe_d = lracl.pipe_radius * 2.0;
+                if (stacks.iter().any(|s| s.fits(lracl.clr, pipe_d))) {
+                    return;
+                }
+                if (tooling.stacks.is_empty()) {
+                    stacks.push(DieStack::generic(lracl.clr, pipe_d));
+                } else {
+                    match tooling.find_stack(lracl.clr, pipe_d) {
+                        Some(s) => stacks.push(s.clone()),
+                        None => errors.push(ToolingError::NoDieStack { row: row, clr: lracl.clr, pipe_d: pipe_d }),
+                    }
+                }
+            }
+        });
+    }
+    if (stacks.len() > head.max_levels) {
+        errors.push(ToolingError::TooManyStacks { count: stacks.len(), max: head.max_levels });
+    }
+    (stacks, errors)
+}
+
+pub fn stack_index(stacks: &Vec<DieStack>, clr: f64) -> Option<usize> {
+    stacks.iter().position(|s| (s.bend_die.clr - clr).abs() < TOLE)
+}
+
+//Head travel between two stacks before a bend, dist is the centerline position where the bend starts
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackChange {
+    pub row: usize,
+    pub dist: f64,
+    pub from: usize,
+    pub to: usize,
+    pub dz: f64,
+}
+
+//Groove center of every stack below the top one, stacks are mounted top down in the given order
+pub fn stack_levels(stacks: &Vec<DieStack>) -> Vec<f64> {
+    let mut levels: Vec<f64> = vec![];
+    let mut z = 0.0;
+    stacks.iter().enumerate().for_each(|(i, s)| {
+        if (i > 0) {
+            z = z + (stacks[i - 1].bend_die.stack_height + s.bend_die.stack_height) / 2.0 + STACK_GAP;
+        }
+        levels.push(z);
+    });
+    levels
+}
+
+//Stack carrying clr with the shortest Z travel from the current one
+pub fn nearest_stack(stacks: &Vec<DieStack>, current: usize, clr: f64, pipe_d: f64) -> Option<usize> {
+    let levels = stack_levels(stacks);
+    let current_z = levels.get(current).cloned().unwrap_or(0.0);
+    stacks.iter().enumerate()
+        .filter(|(_, s)| s.fits(clr, pipe_d))
+        .map(|(i, _)| i)
+        .min_by(|a, b| (levels[*a] - current_z).abs().partial_cmp(&(levels[*b] - current_z).abs()).unwrap())
+}
+
+//All stack changes of the program, the head starts on the top stack
+pub fn stack_changes(lraclr_arr: &Vec<LRACLR>, stacks: &Vec<DieStack>) -> Vec<StackChange> {
+    let levels = stack_levels(stacks);
+    let mut changes: Vec<StackChange> = vec![];
+    let mut current: usize = 0;
+    let mut dist = 0.0;
+    lraclr_arr.iter().enumerate().for_each(|(row, lracl)| {
+        dist = dist + lracl.l;
+        if (lracl.clr > 0.0) {
+            if let Some(to) = nearest_stack(stacks, current, lracl.clr, lracl.pipe_radius * 2.0) {
+                if (to != current) {
+                    changes.push(StackChange { row: row, dist: dist, from: current, to: to, dz: levels[to] - levels[current] });
+                    current = to;
+                }
+            }
+        }
+        dist = dist + lracl.lt();
+    });
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::cnc::test_row;
+
+    #[test]
+    fn same_clr_on_two_levels() {
+        let mut low = DieStack::generic(60.0, 20.0);
+        low.id = "R60-low".to_string();
+        let tooling = Tooling { stacks: vec![DieStack::generic(60.0, 20.0), DieStack::generic(100.0, 20.0), low], mandrels: vec![] };
+        let head = HeadLayout { stacks: vec!["R60.0xD20.0".to_string(), "R100.0xD20.0".to_string(), "R60-low".to_string()], ..HeadLayout::default() };
+        let program = vec![test_row(0, 100.0, 0.0, 90.0, 60.0), test_row(1, 100.0, 0.0, 90.0, 100.0), test_row(2, 100.0, 0.0, 90.0, 60.0), test_row(3, 100.0, 0.0, 0.0, 0.0)];
+        let (stacks, errors) = assign_stacks(&program, &tooling, &head);
+        assert!(errors.is_empty());
+        assert_eq!(stacks.len(), 3);
+        //From the middle level the lower R60 is as near as the top one, the first found wins
+        assert_eq!(nearest_stack(&stacks, 1, 60.0, 20.0), Some(0));
+        assert_eq!(nearest_stack(&stacks, 2, 60.0, 20.0), Some(2));
+    }
+
+    #[test]
+    fn unknown_stack_in_layout() {
+        let head = HeadLayout { stacks: vec!["missing".to_string()], ..HeadLayout::default() };
+        let (stacks, errors) = assign_stacks(&vec![test_row(0, 100.0, 0.0, 90.0, 60.0), test_row(1, 100.0, 0.0, 0.0, 0.0)], &Tooling::default(), &head);
+        assert!(stacks.is_empty());
+        assert_eq!(errors[0], ToolingError::UnknownStack { id: "missing".to_string() });
+        assert!(matches!(errors[1], ToolingError::NoDieStack { row: 0, .. }));
+    }
+}
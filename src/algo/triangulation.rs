@@ -7,13 +7,14 @@ use bevy::asset::RenderAssetUsages;
 use bevy::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
 use bevy::pbr::LightEntity::Point;
 use bevy::prelude::Mesh;
-use crate::algo::cnc::{byt, tot_pipe_len, LRACLR};
+use crate::algo::path::PipePath;
 
 pub const T_INCREMENTS: i64=1000;
-pub fn interpolate_by_t(cmnd: &Vec<LRACLR>, up_dir: &Vector3<f64>) -> (Vec<(Mesh, i64, u64)>, Vec<(Mesh, i64, u64)>) {
+//Meshes along the cached centerline of the program
+pub fn interpolate_by_t(path: &PipePath, pipe_radius: f64) -> (Vec<(Mesh, i64, u64)>, Vec<(Mesh, i64, u64)>) {
     let num_segments = 64;
-    let len: f64 = tot_pipe_len(cmnd);
-    let pipe_radius = cmnd[0].pipe_radius as f32;
+    let len: f64 = path.len();
+    let pipe_radius = pipe_radius as f32;
     let mut ret: Vec<(Mesh, i64, u64)> = vec![];
     let mut ret2: Vec<(Mesh, i64, u64)> = vec![];
     let step: i64 = T_INCREMENTS;
@@ -22,7 +23,8 @@ pub fn interpolate_by_t(cmnd: &Vec<LRACLR>, up_dir: &Vector3<f64>) -> (Vec<(Mesh
     for ti in 1..step {
         let t = ti as f64 / step as f64;
 
-        let ( (pt, x_dir, y_dir, z_dir, rot_deg, id, cp, l,theta, bend_radius)) = byt(t, cmnd, up_dir);
+        let sample = path.at(t);
+        let (pt, id, cp) = (sample.pt, sample.id, sample.cp);
         let mesh = triangulate_pipe(&pt_a, &pt, &cp, pipe_radius, num_segments);
         ret.push((mesh, ti, id));
         pt_a = pt;
@@ -0,0 +1,251 @@
+use std::f64::consts::PI;
+use cgmath::MetricSpace;
+use truck_base::cgmath64::{Point3, Vector3};
+use crate::algo::cnc::{tot_pipe_len, LRACLR, MIN_BEND_ANGLE_DEG};
+use crate::algo::machine::MachineConfig;
+use crate::algo::path::PipePath;
+use crate::algo::tooling::{assign_stacks, DieStack, Tooling};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgramWarning {
+    ShortStraight { row: usize, l: f64, clamp_l: f64 },
+    BendAngle { row: usize, a: f64, max: f64 },
+    ClrRatio { row: usize, ratio: f64, min: f64 },
+    OverStroke { len: f64, stroke: f64 },
+    RotationLimit { row: usize, pos: f64, min: f64, max: f64 },
+    ShortGrip { row: usize, l: f64, min: f64 },
+    SelfIntersection { row: usize, other: usize, clearance: f64 },
+    NoTooling { row: usize, clr: f64, pipe_d: f64 },
+}
+impl ProgramWarning {
+    //Rows of the program the warning belongs to, empty for the whole program
+    pub fn rows(&self) -> Vec<usize> {
+        match self {
+            ProgramWarning::ShortStraight { row, .. } => vec![*row],
+            ProgramWarning::BendAngle { row, .. } => vec![*row],
+            ProgramWarning::ClrRatio { row, .. } => vec![*row],
+            ProgramWarning::OverStroke { .. } => vec![],
+            ProgramWarning::RotationLimit { row, .. } => vec![*row],
+            ProgramWarning::ShortGrip { row, .. } => vec![*row],
+            ProgramWarning::SelfIntersection { row, other, .. } => vec![*row, *other],
+            ProgramWarning::NoTooling { row, .. } => vec![*row],
+        }
+    }
+}
+impl std::fmt::Display for ProgramWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgramWarning::ShortStraight { row, l, clamp_l } => write!(f, "Row {}: straight {:.1} is shorter than the clamp die {:.1}", row, l, clamp_l),
+            ProgramWarning::BendAngle { row, a, max } => write!(f, "Row {}: bend angle {:.1} is over the machine maximum {:.1}", row, a, max),
+            ProgramWarning::ClrRatio { row, ratio, min } => write!(f, "Row {}: CLR/D {:.2} is below the die minimum {:.2}", row, ratio, min),
+            ProgramWarning::OverStroke { len, stroke } => write!(f, "Total length {:.1} is over the carriage stroke {:.1}", len, stroke),
+            ProgramWarning::RotationLimit { row, pos, min, max } => write!(f, "Row {}: rotation to {:.1} is out of the axis limits {:.1}..{:.1}", row, pos, min, max),
+            ProgramWarning::ShortGrip { row, l, min } => write!(f, "Row {}: straight {:.1} is too short to grip, need {:.1}", row, l, min),
+            ProgramWarning::SelfIntersection { row, other, clearance } => write!(f, "Rows {} and {}: pipe runs into itself, clearance {:.1}", row, other, clearance),
+            ProgramWarning::NoTooling { row, clr, pipe_d } => write!(f, "Row {}: no tooling for CLR {:.2} and pipe D {:.2}", row, clr, pipe_d),
+        }
+    }
+}
+
+//Design rules of the program against the machine and the tooling, empty result means the program can run.
+//Pass the machine program, it keeps the die CLRs of the nominal rows
+pub fn validate_program(lraclr_arr: &[LRACLR], machine: &MachineConfig, tooling: &Tooling) -> Vec<ProgramWarning> {
+    let (stacks, _) = assign_stacks(&lraclr_arr.to_vec(), tooling, &machine.head);
+    validate_with_stacks(lraclr_arr, machine, &stacks)
+}
+
+//Same rules against the stacks already mounted on the head
+pub fn validate_with_stacks(lraclr_arr: &[LRACLR], machine: &MachineConfig, stacks: &[DieStack]) -> Vec<ProgramWarning> {
+    let mut warnings: Vec<ProgramWarning> = vec![];
+    if (lraclr_arr.is_empty()) {
+        return warnings;
+    }
+    let program = lraclr_arr.to_vec();
+    let last_row = program.len() - 1;
+    let mut rotation = 0.0;
+
+    program.iter().enumerate().for_each(|(row, lracl)| {
+        let pipe_d = lracl.pipe_radius * 2.0;
+        let is_bend = lracl.clr > 0.0 && lracl.a.abs() > MIN_BEND_ANGLE_DEG;
+        let stack = if (is_bend) { stacks.iter().find(|s| s.fits(lracl.clr, pipe_d)) } else { None };
+        if (is_bend && stack.is_none()) {
+            warnings.push(ProgramWarning::NoTooling { row: row, clr: lracl.clr, pipe_d: pipe_d });
+        }
+
+        //The clamp die holds the straight in front of the bend
+        if let Some(s) = stack {
+            if (row == 0) {
+                if (lracl.l < s.clamp_die.length) {
+                    warnings.push(ProgramWarning::ShortGrip { row: row, l: lracl.l, min: s.clamp_die.length });
+                }
+            } else if (lracl.l < s.clamp_die.length) {
+                warnings.push(ProgramWarning::ShortStraight { row: row, l: lracl.l, clamp_l: s.clamp_die.length });
+            }
+            if (pipe_d > 0.0 && lracl.clr / pipe_d < s.bend_die.min_clr_d) {
+                warnings.push(ProgramWarning::ClrRatio { row: row, ratio: lracl.clr / pipe_d, min: s.bend_die.min_clr_d });
+            }
+        }
+        if (row == last_row && lracl.l < machine.collet_grip) {
+            warnings.push(ProgramWarning::ShortGrip { row: row, l: lracl.l, min: machine.collet_grip });
+        }
+
+        if (is_bend && lracl.a.abs() > machine.max_bend_angle) {
+            warnings.push(ProgramWarning::BendAngle { row: row, a: lracl.a, max: machine.max_bend_angle });
+        }
+
+        rotation = rotation + lracl.r;
+        if (rotation < machine.rotation_min || rotation > machine.rotation_max) {
+            warnings.push(ProgramWarning::RotationLimit { row: row, pos: rotation, min: machine.rotation_min, max: machine.rotation_max });
+        }
+    });
+
+    let len = tot_pipe_len(&program);
+    if (len > machine.carriage_stroke) {
+        warnings.push(ProgramWarning::OverStroke { len: len, stroke: machine.carriage_stroke });
+    }
+
+    warnings
+}
+
+//Clearance of the part shape, one warning per pair of rows with the worst clearance.
+//Checked on the nominal rows, the overbent machine program is not the shape of the part
+pub fn crossing_warnings(nominal: &[LRACLR]) -> Vec<ProgramWarning> {
+    let mut crossings: Vec<ProgramWarning> = vec![];
+    self_intersections(&nominal.to_vec()).iter().for_each(|si| {
+        let (row, other) = si.rows();
+        match crossings.iter_mut().find(|w| matches!(w, ProgramWarning::SelfIntersection { row: r, other: o, .. } if *r == row && *o == other)) {
+            Some(ProgramWarning::SelfIntersection { clearance, .. }) => { *clearance = clearance.min(si.clearance); }
+            _ => crossings.push(ProgramWarning::SelfIntersection { row: row, other: other, clearance: si.clearance }),
+        }
+    });
+    crossings
+}
+
+//Closest approach of two pieces of the bent part, ids of the MainCylinder/BendToro pair from cnc_to_poly
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfIntersection {
+    pub id_a: u64,
+    pub id_b: u64,
+    //Axis distance minus the pipe diameter, negative when the walls overlap
+    pub clearance: f64,
+    pub pt_a: Point3,
+    pub pt_b: Point3,
+}
+impl SelfIntersection {
+    //Program rows of the two pieces, straight and bend of a row share it
+    pub fn rows(&self) -> (usize, usize) {
+        ((self.id_a / 2) as usize, (self.id_b / 2) as usize)
+    }
+}
+
+//Piece pairs whose axes come closer than the pipe diameter.
+//Points closer than half a turn of a D/2 bend along the pipe are skipped, a sound tube can not reach itself there.
+pub fn self_intersections(lraclr_arr: &Vec<LRACLR>) -> Vec<SelfIntersection> {
+    let mut found: Vec<SelfIntersection> = vec![];
+    let pipe_d = match lraclr_arr.first() {
+        Some(f) => f.pipe_radius * 2.0,
+        None => return found,
+    };
+    if (pipe_d <= 0.0) {
+        return found;
+    }
+    let path = PipePath::new(lraclr_arr, &Vector3::new(0.0, 0.0, 1.0));
+    let step = pipe_d / 8.0;
+    let min_gap = PI * pipe_d / 2.0;
+
+    //Centerline samples of every piece with their bounding box
+    let pieces: Vec<(u64, Vec<(f64, Point3)>, Point3, Point3)> = path.spans().iter().filter(|(_, start, end)| end > start).map(|(id, start, end)| {
+        let count = ((end - start) / step).ceil() as usize;
+        let pts: Vec<(f64, Point3)> = (0..=count).map(|k| {
+            let s = (start + k as f64 * step).min(end - 1e-6);
+            (s, path.at_dist(s).pt)
+        }).collect();
+        let mut lo = pts[0].1;
+        let mut hi = pts[0].1;
+        pts.iter().for_each(|(_, p)| {
+            lo = Point3::new(lo.x.min(p.x), lo.y.min(p.y), lo.z.min(p.z));
+            hi = Point3::new(hi.x.max(p.x), hi.y.max(p.y), hi.z.max(p.z));
+        });
+        (*id, pts, lo, hi)
+    }).collect();
+
+    for (i, (id_a, pts_a, lo_a, hi_a)) in pieces.iter().enumerate() {
+        for (id_b, pts_b, lo_b, hi_b) in pieces.iter().skip(i + 1) {
+            //Neighbours share an end point
+            if (id_b - id_a < 2) {
+                continue;
+            }
+            let apart = lo_a.x - hi_b.x >= pipe_d || lo_b.x - hi_a.x >= pipe_d
+                || lo_a.y - hi_b.y >= pipe_d || lo_b.y - hi_a.y >= pipe_d
+                || lo_a.z - hi_b.z >= pipe_d || lo_b.z - hi_a.z >= pipe_d;
+            if (apart) {
+                continue;
+            }
+            let mut closest: Option<(f64, Point3, Point3)> = None;
+            pts_a.iter().for_each(|(sa, pa)| {
+                pts_b.iter().filter(|(sb, _)| sb - sa >= min_gap).for_each(|(_, pb)| {
+                    let d = pa.distance(*pb);
+                    if (d < pipe_d && closest.map_or(true, |(c, _, _)| d < c)) {
+                        closest = Some((d, *pa, *pb));
+                    }
+                });
+            });
+            if let Some((d, pa, pb)) = closest {
+                found.push(SelfIntersection { id_a: *id_a, id_b: *id_b, clearance: d - pipe_d, pt_a: pa, pt_b: pb });
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::cnc::test_row;
+
+    #[test]
+    fn crossing_program() {
+        //Flat spiral, the last straight runs back over the first one at x 60
+        let program = vec![
+            test_row(0, 300.0, 0.0, 90.0, 40.0),
+            test_row(1, 200.0, 0.0, 90.0, 40.0),
+            test_row(2, 200.0, 0.0, 90.0, 40.0),
+            test_row(3, 400.0, 0.0, 0.0, 0.0),
+        ];
+        let found = self_intersections(&program);
+        assert!(!found.is_empty());
+        found.iter().for_each(|si| {
+            assert_eq!(si.rows(), (0, 3));
+            assert!(si.clearance < 0.0);
+        });
+
+        let warnings = crossing_warnings(&program);
+        let crossings: Vec<&ProgramWarning> = warnings.iter().filter(|w| matches!(w, ProgramWarning::SelfIntersection { .. })).collect();
+        assert_eq!(crossings.len(), 1);
+        assert_eq!(crossings[0].rows(), vec![0, 3]);
+    }
+
+    #[test]
+    fn open_program_has_no_crossing() {
+        let program = vec![
+            test_row(0, 300.0, 0.0, 90.0, 40.0),
+            test_row(1, 200.0, 0.0, 90.0, 40.0),
+            test_row(2, 400.0, 0.0, 0.0, 0.0),
+        ];
+        assert!(self_intersections(&program).is_empty());
+    }
+
+    #[test]
+    fn machine_limits() {
+        //Generic stacks without a library, clamp die 60 long
+        let program = vec![
+            test_row(0, 30.0, 0.0, 200.0, 40.0),
+            test_row(1, 4000.0, 0.0, 0.0, 0.0),
+        ];
+        let warnings = validate_program(&program, &MachineConfig::default(), &Tooling::default());
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(warnings[0], ProgramWarning::ShortGrip { row: 0, l: 30.0, min: 60.0 });
+        assert_eq!(warnings[1], ProgramWarning::BendAngle { row: 0, a: 200.0, max: 190.0 });
+        assert!(matches!(warnings[2], ProgramWarning::OverStroke { .. }));
+    }
+}
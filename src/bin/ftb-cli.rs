@@ -0,0 +1,256 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use serde::Serialize;
+use truck_base::cgmath64::Vector3;
+use ftb::algo::{analyze_stp_bodies, select_main_body};
+use ftb::algo::cnc::LRACLR;
+use ftb::algo::collision::{find_collision, CollisionSetup, MachineModel};
+use ftb::algo::compensation::{machine_program, stock_length, CutAllowances, ElongationTable, SpringbackTable};
+use ftb::algo::machine::{load_machine, MachineConfig};
+use ftb::algo::material::MaterialCatalog;
+use ftb::algo::post::{CsvPost, JobInfo, PostProcessor};
+use ftb::algo::tooling::{assign_stacks, load_tooling, Tooling};
+use ftb::algo::validation::{crossing_warnings, validate_program};
+
+const USAGE: &str = "ftb-cli <file.stp | dir> [--out dir] [--machine machine.json] [--tooling tooling.json] [--material name] [--collision assets] [--strict]";
+//Exit code of --strict when every file was analyzed but some have warnings or a collision
+const EXIT_WARNINGS: u8 = 3;
+
+struct Options {
+    input: PathBuf,
+    out: Option<PathBuf>,
+    machine: MachineConfig,
+    tooling: Tooling,
+    material: String,
+    //Asset folder with the machine models, enables the collision check
+    collision: Option<PathBuf>,
+    strict: bool,
+}
+
+#[derive(Serialize)]
+struct FileReport {
+    file: String,
+    material: String,
+    diameter: f64,
+    thickness: Option<f64>,
+    bend_count: usize,
+    total_length: f64,
+    cut_length: f64,
+    warnings: Vec<String>,
+    collision: Option<String>,
+    segments: Vec<LRACLR>,
+    //Springback and elongation compensated rows written to the CSV
+    program: Vec<LRACLR>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut input: Option<PathBuf> = None;
+    let mut out: Option<PathBuf> = None;
+    let mut machine = MachineConfig::default();
+    let mut tooling = Tooling::default();
+    let mut material = "S235".to_string();
+    let mut collision: Option<PathBuf> = None;
+    let mut strict = false;
+    let mut i = 0;
+    while (i < args.len()) {
+        let value = || args.get(i + 1).cloned().ok_or(format!("{} needs a value", args[i]));
+        match args[i].as_str() {
+            "--out" => { out = Some(PathBuf::from(value()?)); i += 1; }
+            "--machine" => {
+                let path = PathBuf::from(value()?);
+                machine = load_machine(&path).map_err(|e| format!("{:?}: {}", path, e))?;
+                i += 1;
+            }
+            "--tooling" => {
+                let path = PathBuf::from(value()?);
+                tooling = load_tooling(&path).map_err(|e| format!("{:?}: {}", path, e))?;
+                i += 1;
+            }
+            "--material" => { material = value()?; i += 1; }
+            "--collision" => { collision = Some(PathBuf::from(value()?)); i += 1; }
+            "--strict" => { strict = true; }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            arg => {
+                if (input.is_some()) {
+                    return Err(format!("unexpected argument {}\n{}", arg, USAGE));
+                }
+                input = Some(PathBuf::from(arg));
+            }
+        }
+        i += 1;
+    }
+    match input {
+        None => Err(USAGE.to_string()),
+        Some(input) => Ok(Options { input: input, out: out, machine: machine, tooling: tooling, material: material, collision: collision, strict: strict }),
+    }
+}
+
+fn is_step(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.eq_ignore_ascii_case("stp") || ext.eq_ignore_ascii_case("step"),
+        None => false,
+    }
+}
+
+fn step_files(input: &PathBuf) -> Result<Vec<PathBuf>, String> {
+    if (input.is_dir()) {
+        let entries = fs::read_dir(input).map_err(|e| format!("{:?}: {}", input, e))?;
+        let mut files: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file() && is_step(p)).collect();
+        files.sort();
+        Ok(files)
+    } else {
+        Ok(vec![input.clone()])
+    }
+}
+
+fn analyze_file(path: &PathBuf, opts: &Options) -> Result<FileReport, String> {
+    let stp = fs::read(path).map_err(|e| e.to_string())?;
+    let bodies = analyze_stp_bodies(&stp).map_err(|e| e.to_string())?;
+    let (body, body_warning) = select_main_body(&bodies).map_err(|e| e.to_string())?;
+    let family = MaterialCatalog::default().family(&opts.material);
+    let cut_length = stock_length(&body.segments, &ElongationTable::default(), &family, &CutAllowances::default());
+    let program = machine_program(&body.segments, &SpringbackTable::default(), &ElongationTable::default(), &family);
+    let (stacks, _) = assign_stacks(&body.segments, &opts.tooling, &opts.machine.head);
+    let mut warnings: Vec<String> = body_warning.iter().map(|w| w.to_string()).collect();
+    validate_program(&program, &opts.machine, &opts.tooling).iter().for_each(|w| warnings.push(w.to_string()));
+    crossing_warnings(&body.segments).iter().for_each(|w| warnings.push(w.to_string()));
+    let collision = match &opts.collision {
+        Some(assets) => {
+            let model = MachineModel::load(assets, &stacks, body.outer_radius * 2.0).map_err(|e| e.to_string())?;
+            find_collision(&program, &Vector3::new(0.0, 0.0, 1.0), &model, &opts.machine, &CollisionSetup::default()).map(|c| c.to_string())
+        }
+        None => None,
+    };
+    Ok(FileReport {
+        file: path.to_string_lossy().to_string(),
+        material: opts.material.clone(),
+        diameter: body.outer_radius * 2.0,
+        thickness: body.wall_thickness(),
+        bend_count: body.bend_count(),
+        total_length: body.total_len(),
+        cut_length: cut_length,
+        warnings: warnings,
+        collision: collision,
+        segments: body.segments.clone(),
+        program: program,
+    })
+}
+
+fn write_report(path: &PathBuf, report: &FileReport, out: &Option<PathBuf>) -> Result<(), String> {
+    let dir = match out {
+        Some(d) => d.clone(),
+        None => path.parent().map(|p| p.to_path_buf()).unwrap_or(PathBuf::from(".")),
+    };
+    fs::create_dir_all(&dir).map_err(|e| format!("{:?}: {}", dir, e))?;
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or("part".to_string());
+    let csv_path = dir.join(format!("{}.csv", stem));
+    let job = JobInfo {
+        name: stem.clone(),
+        material: report.material.clone(),
+        diameter: report.diameter,
+        thickness: report.thickness.unwrap_or(0.0),
+        cut_length: report.cut_length,
+        bends: vec![],
+        loads: vec![],
+    };
+    fs::write(&csv_path, CsvPost { cut_length: false }.generate(&report.program, &job)).map_err(|e| format!("{:?}: {}", csv_path, e))?;
+    let json_path = dir.join(format!("{}.json", stem));
+    let json = serde_json::to_string_pretty(report).map_err(|e| e.to_string())?;
+    fs::write(&json_path, json).map_err(|e| format!("{:?}: {}", json_path, e))
+}
+
+fn main() -> ExitCode {
+    let opts = match parse_args() {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+    let files = match step_files(&opts.input) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+    if (files.is_empty()) {
+        eprintln!("{:?}: no STEP files", opts.input);
+        return ExitCode::from(2);
+    }
+
+    let mut failed = 0;
+    let mut flagged = 0;
+    files.iter().for_each(|path| {
+        match analyze_file(path, &opts).and_then(|report| write_report(path, &report, &opts.out).map(|_| report)) {
+            Ok(report) => {
+                println!("{}: bends {} cut {:.1} mm warnings {}", report.file, report.bend_count, report.cut_length, report.warnings.len());
+                report.warnings.iter().for_each(|w| println!("  {}", w));
+                if let Some(c) = &report.collision {
+                    println!("  Collision: {}", c);
+                }
+                if (!report.warnings.is_empty() || report.collision.is_some()) {
+                    flagged += 1;
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path.to_string_lossy(), e);
+                failed += 1;
+            }
+        }
+    });
+    println!("{} files, {} failed, {} with warnings", files.len(), failed, flagged);
+    if (failed > 0) {
+        ExitCode::FAILURE
+    } else if (opts.strict && flagged > 0) {
+        ExitCode::from(EXIT_WARNINGS)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ftb::algo::cnc::parse_csv;
+
+    fn demo_options() -> Options {
+        Options {
+            input: PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/files/2.stp"),
+            out: None,
+            machine: MachineConfig::default(),
+            tooling: Tooling::default(),
+            material: "S235".to_string(),
+            collision: None,
+            strict: false,
+        }
+    }
+
+    #[test]
+    fn step_extensions() {
+        assert!(is_step(Path::new("part.STEP")));
+        assert!(is_step(Path::new("part.stp")));
+        assert!(!is_step(Path::new("part.csv")));
+        assert!(!is_step(Path::new("part")));
+    }
+
+    #[test]
+    fn demo_report() {
+        let opts = demo_options();
+        let report = analyze_file(&opts.input, &opts).unwrap();
+        assert_eq!(report.bend_count, 7);
+        assert!((report.diameter - 33.7).abs() < 1e-3);
+        assert!((report.thickness.unwrap() - 2.0).abs() < 1e-3);
+        assert_eq!(report.program.len(), report.segments.len());
+        assert!(report.cut_length > 0.0 && report.cut_length <= report.total_length);
+
+        let out = std::env::temp_dir().join("ftb-cli-demo-report");
+        write_report(&opts.input, &report, &Some(out.clone())).unwrap();
+        let csv = fs::read_to_string(out.join("2.csv")).unwrap();
+        assert_eq!(parse_csv(&csv, 16.85).unwrap().len(), report.program.len());
+        assert!(out.join("2.json").exists());
+        let _ = fs::remove_dir_all(&out);
+    }
+}
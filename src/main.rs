@@ -19,7 +19,7 @@ use bevy::window::PrimaryWindow;
 use winit::window::Icon;
 
 mod states;
-mod algo;
+use ftb::algo;
 mod ui;
 mod render;
 
@@ -1,30 +1,14 @@
-use std::f32::consts::PI;
 use bevy::app::{App, Plugin, Update};
 use bevy::prelude::*;
 use bevy_ecs::prelude::*;
+use crate::algo::collision::{die_placement, MachinePart, CLAMP_DIE_OFFSET, DIE_OFFSET, MACHINE_ASSETS, PRESSURE_DIE_OFFSET};
+use crate::algo::kinematics::MachineNode;
 use crate::algo::solids::generate_roller;
+use crate::algo::tooling::{stack_levels, DieStack};
 use crate::states::pipe_control::PipeSpecification;
 use crate::states::scene_control::{AppMode, Resettable};
 use crate::states::state_machine::{MachineRegisters, RobotState};
 
-#[derive(Component)]
-pub enum MachinePartKind {
-    DayamaAlt,
-    DayamamKizak,
-    DayamamKizakArka,
-    MALAFA,
-    MENGENE,
-    MengeneAlt,
-    PALKA2M,
-    PALKAM,
-    PENS,
-    SASI,
-    PALKA3,
-    DornA,
-    DornB,
-    Static,
-}
-
 pub struct MachineControlPlugin;
 
 impl Plugin for MachineControlPlugin {
@@ -37,7 +21,7 @@ impl Plugin for MachineControlPlugin {
       //  app.add_systems(OnEnter(AppMode::StandBy), crate::states::scene_control::stand_by);
        // app.add_systems(OnEnter(AppMode::Simulating), crate::states::scene_control::simulating);
       //  app.add_systems(Update, (cad_camera_controller, crate::states::scene_control::draw_gizmos));
-        app.add_systems(Update, update_machine);
+        app.add_systems(Update, (update_machine, draw_collision));
     }
 }
 
@@ -94,233 +78,160 @@ fn setup_machine_hierarchy(mut commands: Commands,
         ..default()
     });
 
-    let mut roller_a = generate_roller(pipe_spec.roller_a, pipe_spec.diameter);
-    let roller_b = generate_roller(pipe_spec.roller_b, pipe_spec.diameter);
-
-    //let roller_a_scaled=roller_a[0].clone().transformed_by( Transform::from_xyz(3058.0, 289.37, 154.89)).rotated_by(  Quat::from_rotation_y(PI)*Quat::from_rotation_z(PI / 2.0)).scaled_by(Vec3::splat(0.001));
-    let mut roller_a_positioned=roller_a[0].clone()
-        .translated_by(Vec3::new(3058.0, 289.37, 154.89))
-        .rotated_by(  Quat::from_rotation_y(PI)*Quat::from_rotation_z(PI / 2.0))
-        .scaled_by(Vec3::splat(0.001))
-        .translated_by(Vec3::new(-0.199, 0.0, 0.015));
-
-    let roller_a_helper_positioned=roller_a[1].clone()
-        .translated_by(Vec3::new(3058.0, 289.37, 154.89))
-        .rotated_by(  Quat::from_rotation_y(PI)*Quat::from_rotation_z(PI / 2.0))
-        .scaled_by(Vec3::splat(0.001))
-        .translated_by(Vec3::new(-0.199, 0.0, 0.015));
-
-    let roller_a_helper_b_positioned=roller_a[2].clone()
-        .translated_by(Vec3::new(3058.0, 289.37, 154.89))
-        .rotated_by(  Quat::from_rotation_y(PI)*Quat::from_rotation_z(PI / 2.0))
-        .scaled_by(Vec3::splat(0.001))
-        .translated_by(Vec3::new(-0.131, 0.0, 0.015));
-
-    let roller_a_support_positioned=roller_a[3].clone()
-        .translated_by(Vec3::new(3058.0, 289.37, 154.89))
-        .rotated_by(  Quat::from_rotation_y(PI)*Quat::from_rotation_z(PI / 2.0))
-        .scaled_by(Vec3::splat(0.001))
-        .translated_by(Vec3::new( -0.188, -0.304,0.015));
-
-
-    let roller_b_positioned=roller_b[0].clone()
-        .translated_by(Vec3::new(3058.0, 289.37, 154.89))
-        .rotated_by(  Quat::from_rotation_y(PI)*Quat::from_rotation_z(PI / 2.0))
-        .scaled_by(Vec3::splat(0.001))
-        .translated_by(Vec3::new(-0.199, 0.0, -0.037));
-
-    let roller_b_helper_positioned=roller_b[1].clone()
-        .translated_by(Vec3::new(3058.0, 289.37, 154.89))
-        .rotated_by(  Quat::from_rotation_y(PI)*Quat::from_rotation_z(PI / 2.0))
-        .scaled_by(Vec3::splat(0.001))
-        .translated_by(Vec3::new(-0.199, 0.0, -0.037));
-
-    let roller_b_helper_b_positioned=roller_b[2].clone()
-        .translated_by(Vec3::new(3058.0, 289.37, 154.89))
-        .rotated_by(  Quat::from_rotation_y(PI)*Quat::from_rotation_z(PI / 2.0))
-        .scaled_by(Vec3::splat(0.001))
-        .translated_by(Vec3::new(-0.131, 0.0, -0.037));
-
-    let roller_b_support_positioned=roller_b[3].clone()
-        .translated_by(Vec3::new(3058.0, 289.37, 154.89))
-        .rotated_by(  Quat::from_rotation_y(PI)*Quat::from_rotation_z(PI / 2.0))
-        .scaled_by(Vec3::splat(0.001))
-        .translated_by(Vec3::new( -0.188, -0.304,-0.037));
-
-
-    let handle_roller_a = meshes.add(roller_a_positioned);
-    let handle_roller_helper_a_a = meshes.add(roller_a_helper_positioned);
-    let handle_roller_helper_a_b = meshes.add(roller_a_helper_b_positioned);
-    let roller_a_support_handler = meshes.add(roller_a_support_positioned);
-
-    let handle_roller_b = meshes.add(roller_b_positioned);
-    let handle_roller_helper_b = meshes.add(roller_b_helper_positioned);
-    let handle_roller_helper_b_b = meshes.add(roller_b_helper_b_positioned);
-    let roller_b_support_handler = meshes.add(roller_b_support_positioned);
-
+    let generic_stack = vec![DieStack::generic(pipe_spec.diameter * 1.5, pipe_spec.diameter)];
+    let stacks = if pipe_spec.stacks.is_empty() { &generic_stack } else { &pipe_spec.stacks };
+    //Stacks are mounted top down, the top one is at the pipe axis when the head is in the start position
+    let levels = stack_levels(stacks);
+    let die_materials = [material_handle_red.clone(), material_handle_gray.clone()];
+    let helper_materials = [material_handle_green.clone(), material_handle_blue.clone()];
+    let support_materials = [material_handle_aqua.clone(), material_handle_yellow.clone()];
+
+    let mut die_handles: Vec<(Handle<Mesh>, Handle<StandardMaterial>)> = vec![];
+    let mut helper_handles: Vec<(Handle<Mesh>, Handle<StandardMaterial>)> = vec![];
+    let mut support_handles: Vec<(Handle<Mesh>, Handle<StandardMaterial>)> = vec![];
+    stacks.iter().enumerate().for_each(|(i, stack)| {
+        let roller = generate_roller(stack, pipe_spec.diameter);
+        //Same placement as the collision model
+        let roller_positioned=roller[0].clone().transformed_by(Transform::from_matrix(die_placement(DIE_OFFSET, levels[i])));
+        let roller_helper_positioned=roller[1].clone().transformed_by(Transform::from_matrix(die_placement(DIE_OFFSET, levels[i])));
+        let roller_helper_b_positioned=roller[2].clone().transformed_by(Transform::from_matrix(die_placement(CLAMP_DIE_OFFSET, levels[i])));
+        let roller_support_positioned=roller[3].clone().transformed_by(Transform::from_matrix(die_placement(PRESSURE_DIE_OFFSET, levels[i])));
+
+        let die_material = die_materials[i % 2].clone();
+        die_handles.push((meshes.add(roller_positioned), die_material.clone()));
+        die_handles.push((meshes.add(roller_helper_positioned), die_material));
+        helper_handles.push((meshes.add(roller_helper_b_positioned), helper_materials[i % 2].clone()));
+        support_handles.push((meshes.add(roller_support_positioned), support_materials[i % 2].clone()));
+    });
 
-    let dayama_alt: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/dayama_alt2.glb")));
-    let dayamam_kizak: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/dayamam_kizak3.glb")));
-    let dayamam_kizak_arka: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/dayamam_kizak_arka3.glb")));
-    let malafa: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/malafa.glb")));
-    let mengene: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/mengene2.glb")));
-    let mengene_alt: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/mengene_alt2.glb")));
-    let palka2m: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/palka2m.glb")));
-    let palkam: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/palkam.glb")));
-    let pens: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/pens.glb")));
-    let sasi: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/sasi.glb")));
-    let palka3: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("machines/m2/palka3.glb")));
 
+    let dayama_alt: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::DayamaAlt))));
+    let dayamam_kizak: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::DayamamKizak))));
+    let dayamam_kizak_arka: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::DayamamKizakArka))));
+    let malafa: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::MALAFA))));
+    let mengene: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::MENGENE))));
+    let mengene_alt: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::MengeneAlt))));
+    let palka2m: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::PALKA2M))));
+    let palkam: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::PALKAM))));
+    let pens: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::PENS))));
+    let sasi: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::SASI))));
+    let palka3: SceneRoot = SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset(model_path(MachinePart::PALKA3))));
+
+    //Every node gets its transform from the shared kinematic chain, see update_machine
+    let axes = machine_registers.axis_registers();
+    let node_transform = |node: MachineNode| Transform::from_matrix(axes.local_matrix(node));
     commands.spawn((
-        Transform::from_xyz(-3058.0, -289.37, -154.89)
-            .with_rotation(Quat::from_rotation_y(PI) * Quat::from_rotation_z(PI / 2.0))
-            .with_scale(Vec3::splat(1000.0)),
+        node_transform(MachineNode::Root),
         Visibility::default(),
         InheritedVisibility::default(),
-        MachinePartKind::Static,
+        MachineNode::Root,
         Resettable,
     )).with_children(|parent| {
         parent.spawn((
             sasi,
             Transform::IDENTITY, // Локально стоит в нуле
-            MachinePartKind::SASI,
         ));
 
         parent.spawn((
             malafa,
-            Transform::IDENTITY,  // Стартовая позиция каретки
-            MachinePartKind::MALAFA,
+            Transform::IDENTITY,
         ));
 
         // --- ДОРН (Mandrel) ---
         parent.spawn((
             pens,
-            Transform::from_xyz(0.00, 0.0, 0.0),  //3.525
-            MachinePartKind::PENS,
+            node_transform(MachineNode::Pusher),
+            MachineNode::Pusher,
         ));
         // --- Z MOVEMENT BLOCK ---
         parent.spawn((
             palka2m,
-            Transform::IDENTITY,  // Стартовая позиция каретки
-            MachinePartKind::PALKA2M,
+            node_transform(MachineNode::ZBlock),
+            MachineNode::ZBlock,
         )).with_children(|z_movement| {
 
             // --- Y MOVEMENT BLOCK ---palkam
             z_movement.spawn((
                 palkam,
-                Transform::IDENTITY,  // Стартовая позиция каретки
-                MachinePartKind::DayamaAlt,
+                node_transform(MachineNode::YBlock),
+                MachineNode::YBlock,
             ));
             // --- Y MOVEMENT BLOCK ---
             z_movement.spawn((
                 dayama_alt,
-                Transform::IDENTITY,  // Стартовая позиция каретки
-                MachinePartKind::PALKAM,
+                node_transform(MachineNode::YBlock),
+                MachineNode::YBlock,
             )).with_children(|y_movement| {
                 y_movement.spawn((
                     palka3,
-                    Transform::from_xyz(0.0903719, 3.058, -0.3658858), //3.525
-                    MachinePartKind::PALKA3,
+                    node_transform(MachineNode::Palka3),
+                    MachineNode::Palka3,
                 ));
 
 
                 // --- Y MOVEMENT SUB BLOCK A---
                 y_movement.spawn((
                     dayamam_kizak_arka,
-                    Transform::IDENTITY,  // Стартовая позиция каретки
-                    MachinePartKind::DayamamKizakArka,
+                    node_transform(MachineNode::PressureSlide),
+                    MachineNode::PressureSlide,
                 )).with_children(|y_subpart| {
                     // --- A SUB BLOCK---
                     y_subpart.spawn((
                         dayamam_kizak,
-                        Transform::IDENTITY,
-                        MachinePartKind::DayamamKizak,
-                    ));
-
-                    y_subpart.spawn((
-                        Mesh3d(roller_a_support_handler),
-                        MeshMaterial3d(material_handle_aqua.clone()),
-                        Transform::IDENTITY,
-                        MachinePartKind::DayamamKizak,
-                        Visibility::Visible,
-                    ));
-
-                    y_subpart.spawn((
-                        Mesh3d(roller_b_support_handler),
-                        MeshMaterial3d(material_handle_yellow.clone()),
-                        Transform::IDENTITY,
-                        MachinePartKind::DayamamKizak,
-                        Visibility::Visible,
+                        node_transform(MachineNode::PressureDie),
+                        MachineNode::PressureDie,
                     ));
 
+                    support_handles.iter().for_each(|(mesh, material)| {
+                        y_subpart.spawn((
+                            Mesh3d(mesh.clone()),
+                            MeshMaterial3d(material.clone()),
+                            node_transform(MachineNode::PressureDie),
+                            MachineNode::PressureDie,
+                            Visibility::Visible,
+                        ));
+                    });
                 });
 
-                let rotation_pivot_offset = Vec3::new(0.0903719, 3.0580, 0.0);
                 y_movement.spawn((
                     // Ставим пивот в точку вращения
-                    Transform::from_translation(rotation_pivot_offset),
+                    node_transform(MachineNode::BendPivot),
                     Visibility::default(),
                     InheritedVisibility::default(),
-                    MachinePartKind::MengeneAlt,
+                    MachineNode::BendPivot,
                 )).with_children(|pivot| {
                     // --- MAIN ROTATION BLOCK ---
                     pivot.spawn((
                         mengene_alt,
-                        Transform::from_translation(-rotation_pivot_offset),
+                        node_transform(MachineNode::BendArm),
+                        MachineNode::BendArm,
                     )).with_children(|rot_subb| {
 
                         // --- ROTATION SUBBLOCK ---
                         rot_subb.spawn((
                             mengene,
-                            Transform::IDENTITY,  // Стартовая позиция каретки
-                            MachinePartKind::MENGENE,
-                        ));
-
-                        rot_subb.spawn((
-                            Mesh3d(handle_roller_a),
-                            MeshMaterial3d(material_handle_red.clone()),
-                            Transform::IDENTITY,
-                            MachinePartKind::DornA,
-                            Visibility::Visible,
-                        ));
-                        rot_subb.spawn((
-                            Mesh3d(handle_roller_helper_a_a),
-                            MeshMaterial3d(material_handle_red.clone()),
-                            Transform::IDENTITY,
-                            MachinePartKind::DornA,
-                            Visibility::Visible,
-                        ));
-                        rot_subb.spawn((
-                            Mesh3d(handle_roller_helper_a_b),
-                            MeshMaterial3d(material_handle_green.clone()),
-                            Transform::IDENTITY,
-                            MachinePartKind::MENGENE,
-                            Visibility::Visible,
-                        ));
-
-
-                        rot_subb.spawn((
-                            Mesh3d(handle_roller_b),
-                            MeshMaterial3d(material_handle_gray.clone()),
-                            Transform::IDENTITY,
-                            MachinePartKind::DornA,
-                            Visibility::Visible,
-                        ));
-                        rot_subb.spawn((
-                            Mesh3d(handle_roller_helper_b),
-                            MeshMaterial3d(material_handle_gray.clone()),
-                            Transform::IDENTITY,
-                            MachinePartKind::DornA,
-                            Visibility::Visible,
+                            node_transform(MachineNode::ClampSlide),
+                            MachineNode::ClampSlide,
                         ));
 
-                        rot_subb.spawn((
-                            Mesh3d(handle_roller_helper_b_b),
-                            MeshMaterial3d(material_handle_blue.clone()),
-                            Transform::IDENTITY,
-                            MachinePartKind::MENGENE,
-                            Visibility::Visible,
-                        ));
+                        //Bend dies are fixed on the arm
+                        die_handles.iter().for_each(|(mesh, material)| {
+                            rot_subb.spawn((
+                                Mesh3d(mesh.clone()),
+                                MeshMaterial3d(material.clone()),
+                                Transform::IDENTITY,
+                                Visibility::Visible,
+                            ));
+                        });
+                        helper_handles.iter().for_each(|(mesh, material)| {
+                            rot_subb.spawn((
+                                Mesh3d(mesh.clone()),
+                                MeshMaterial3d(material.clone()),
+                                node_transform(MachineNode::ClampSlide),
+                                MachineNode::ClampSlide,
+                                Visibility::Visible,
+                            ));
+                        });
 
                     });
                 });
@@ -329,10 +240,24 @@ fn setup_machine_hierarchy(mut commands: Commands,
     });
 }
 
-fn debug_draw_pivot(mut gizmos: Gizmos, query: Query<(&GlobalTransform, &MachinePartKind)>) {
-    for (transform, part_type) in query.iter() {
+fn model_path(part: MachinePart) -> String {
+    format!("{}/{}", MACHINE_ASSETS, part.file().unwrap_or_default())
+}
 
-        if let MachinePartKind::MengeneAlt = part_type {
+//Part box and contact point of the first collision, shown at the program position of the contact
+fn draw_collision(mut gizmos: Gizmos, pipe_spec: Res<PipeSpecification>) {
+    if let Some(collision) = &pipe_spec.collision {
+        let (lo, hi) = collision.bounds;
+        let color = Color::srgb(1.0, 0.0, 0.0);
+        gizmos.cuboid(Transform::from_translation((lo + hi) / 2.0).with_scale(hi - lo), color);
+        gizmos.sphere(Isometry3d::from_translation(collision.point), (pipe_spec.diameter / 2.0) as f32, color);
+    }
+}
+
+fn debug_draw_pivot(mut gizmos: Gizmos, query: Query<(&GlobalTransform, &MachineNode)>) {
+    for (transform, node) in query.iter() {
+
+        if let MachineNode::BendPivot = node {
             gizmos.axes(*transform, 1.5);
         }
     }
@@ -340,76 +265,21 @@ fn debug_draw_pivot(mut gizmos: Gizmos, query: Query<(&GlobalTransform, &Machine
 
 fn setup_start_positions(
     mut machine_registers: ResMut<MachineRegisters>,
-    mut query: Query<(&mut Transform, &MachinePartKind)>,
+    mut query: Query<(&mut Transform, &MachineNode)>,
 ) {
     println!("setup_start_positions {:?} {:?}",machine_registers.dx,machine_registers.mov_pusher_x);
-
-    for (mut transform, part_type) in query.iter_mut() {
-        match part_type {
-            MachinePartKind::PENS => {
-                transform.translation.y = (machine_registers.dx) as f32 / 1000.0 + machine_registers.mov_pusher_x as f32;//- 0.615
-            }
-            MachinePartKind::MengeneAlt => {
-                transform.rotation = Quat::from_rotation_z(machine_registers.bend_angle as f32);
-            }
-            MachinePartKind::PALKA2M => {
-                transform.translation.z = machine_registers.mov_z as f32;
-            }
-            MachinePartKind::DayamamKizakArka => {
-                transform.translation.x = machine_registers.mov_static_y as f32;
-            }
-            MachinePartKind::DayamamKizak => {
-                transform.translation.y = machine_registers.mov_static_x as f32;
-            }
-
-            MachinePartKind::MENGENE => {
-                transform.translation.x = machine_registers.mov_rotated_y as f32 + machine_registers.mov_rotated_y_clamp as f32;
-            }
-
-            MachinePartKind::DayamaAlt | MachinePartKind::PALKAM => {
-                transform.translation.x = machine_registers.mov_y as f32;
-            }
-
-            _ => {}
-        }
+    let axes = machine_registers.axis_registers();
+    for (mut transform, node) in query.iter_mut() {
+        *transform = Transform::from_matrix(axes.local_matrix(*node));
     }
 }
 
 fn update_machine(
     mut machine_registers: ResMut<MachineRegisters>,
-    mut query: Query<(&mut Transform, &MachinePartKind)>,
+    mut query: Query<(&mut Transform, &MachineNode)>,
 ) {
-
-
-    for (mut transform, part_type) in query.iter_mut() {
-        match part_type {
-            MachinePartKind::PENS => {
-               // transform.translation.y = (machine_registers.dx+machine_registers.mov_pusher_x) as f32 / 1000.0  + machine_registers.mov_pusher_x as f32;;
-                transform.translation.y = (machine_registers.dx) as f32 / 1000.0  + machine_registers.mov_pusher_x as f32;;
-            }
-            MachinePartKind::MengeneAlt => {
-                transform.rotation = Quat::from_rotation_z(machine_registers.bend_angle as f32);
-            }
-            MachinePartKind::PALKA2M => {
-                transform.translation.z = machine_registers.mov_z as f32;// + machine_registers.mov_z_clamp as f32;
-                //transform.translation.z += machine_registers.mov_z_clamp as f32;
-            }
-            MachinePartKind::DayamamKizakArka => {
-                transform.translation.x = machine_registers.mov_static_y as f32 + machine_registers.mov_static_y_clamp as f32;
-            }
-            MachinePartKind::DayamamKizak => {
-                transform.translation.y = machine_registers.mov_static_x as f32;
-            }
-
-            MachinePartKind::MENGENE => {
-                transform.translation.x = machine_registers.mov_rotated_y as f32 + machine_registers.mov_rotated_y_clamp as f32;
-            }
-
-            MachinePartKind::DayamaAlt | MachinePartKind::PALKAM => {
-                transform.translation.x = machine_registers.mov_y as f32 + machine_registers.mov_y_clamp as f32;;
-            }
-
-            _ => {}
-        }
+    let axes = machine_registers.axis_registers();
+    for (mut transform, node) in query.iter_mut() {
+        *transform = Transform::from_matrix(axes.local_matrix(*node));
     }
 }
\ No newline at end of file
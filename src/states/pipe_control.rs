@@ -1,9 +1,22 @@
-use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use bevy::asset::io::file::FileAssetReader;
 use bevy::prelude::*;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
 use is_odd::IsOdd;
-use ordered_float::OrderedFloat;
-use crate::algo::analyze_stp;
-use crate::algo::cnc::{byt, LRACLR};
+use crate::algo::{analyze_stp_bodies, select_main_body, PipeBody, StepAnalysisError};
+use crate::algo::cnc::{bend_loads, cycle_time, load_csv, load_xyz, BendLoad, CycleTime, ImportError, LRACLR};
+use crate::algo::collision::{find_collision, Collision, CollisionSetup, MachineModel};
+use crate::algo::compensation::{machine_program, stock_length, CutAllowances, ElongationTable, SpringbackTable};
+use crate::algo::post::JobInfo;
+use crate::algo::deformation::{program_deformation, BendDeformation, DeformationLimits};
+use crate::algo::nesting::{NestPart, NestPlan, NestingSetup};
+use crate::algo::material::{load_materials, MaterialCatalog, MATERIAL_FILE};
+use crate::algo::machine::{load_machine, MachineConfig, MACHINE_FILE};
+use crate::algo::validation::{crossing_warnings, validate_with_stacks, ProgramWarning};
+use crate::algo::tooling::{assign_stacks, load_tooling, DieStack, Tooling, ToolingError, TOOLING_FILE};
+use crate::algo::project::{ProjectFile, PROJECT_VERSION};
+use crate::algo::path::PipePath;
 use crate::algo::triangulation::{interpolate_by_t};
 use crate::states::scene_control::{AppMode, Resettable};
 use crate::states::state_machine::{MachineRegisters, RobotState};
@@ -14,11 +27,38 @@ pub struct PipeSpecification {
     pub segments: Vec<LRACLR>,
     pub diameter: f64,
     pub thickness: f64,
+    pub inner_diameter: Option<f64>,
     pub material_changed: bool,
     pub len: f64,
-    pub roller_a:f64,
-    pub roller_b:f64,
-    pub roller_c:f64,
+    pub machine: MachineConfig,
+    pub tooling: Tooling,
+    pub stacks: Vec<DieStack>,
+    pub tool_errors: Vec<ToolingError>,
+    pub import_error: Option<String>,
+    //Main body was picked among equal candidates
+    pub body_warning: Option<StepAnalysisError>,
+    pub bodies: Vec<PipeBody>,
+    pub show_body_picker: bool,
+    pub jobs: VecDeque<PipeBody>,
+    pub material: String,
+    pub materials: MaterialCatalog,
+    pub deformation_limits: DeformationLimits,
+    pub allowances: CutAllowances,
+    pub nesting: NestingSetup,
+    pub nest_plan: Option<NestPlan>,
+    pub show_nesting: bool,
+    //First pipe to machine contact of the last check, cleared on every program edit
+    pub collision: Option<Collision>,
+    pub collision_checked: bool,
+    //Check in progress on the compute pool, dropped on every program edit
+    pub collision_task: Option<Task<Result<Option<Collision>, ImportError>>>,
+    //Validation of the current program, recomputed after every program, machine or tooling edit
+    pub warnings: Vec<ProgramWarning>,
+    pub warnings_checked: bool,
+    pub source_path: Option<PathBuf>,
+    pub source_stp: Option<Vec<u8>>,
+    pub springback: SpringbackTable,
+    pub elongation: ElongationTable,
 }
 impl Default for PipeSpecification {
     fn default() -> Self {
@@ -46,62 +86,259 @@ impl Default for PipeSpecification {
             segments: vec![],
             diameter: 50.0,
             thickness: 2.0,
+            inner_diameter: None,
             material_changed: true,
             len:200.0,
-            roller_a: -10.0,
-            roller_b: -10.0,
-            roller_c: -10.0,
+            machine: load_machine(&PathBuf::from(MACHINE_FILE)).unwrap_or_default(),
+            tooling: load_tooling(&PathBuf::from(TOOLING_FILE)).unwrap_or_default(),
+            stacks: vec![],
+            tool_errors: vec![],
+            import_error: None,
+            body_warning: None,
+            bodies: vec![],
+            show_body_picker: false,
+            jobs: VecDeque::new(),
+            material: "S235".to_string(),
+            materials: load_materials(&PathBuf::from(MATERIAL_FILE)).unwrap_or_default(),
+            deformation_limits: DeformationLimits::default(),
+            allowances: CutAllowances::default(),
+            nesting: NestingSetup::default(),
+            nest_plan: None,
+            show_nesting: false,
+            collision: None,
+            collision_checked: false,
+            collision_task: None,
+            warnings: vec![],
+            warnings_checked: false,
+            source_path: None,
+            source_stp: None,
+            springback: SpringbackTable::default(),
+            elongation: ElongationTable::default(),
         };
         let startup_pipe=p.demos[DEFAULT_DEMO].clone();
-        p.init_pipe(&startup_pipe);
+        match p.init_pipe(&startup_pipe) {
+            Ok(_) => {}
+            Err(e) => {
+                warn!("demo {:?} {}", DEFAULT_DEMO, e);
+                p.import_error = Some(e.to_string());
+            }
+        }
         p
     }
 }
 
 impl PipeSpecification {
-    pub fn init_pipe(&mut self,stp:&Vec<u8>){
-        let lraclr_arr: Vec<LRACLR> = analyze_stp(&stp);
+    pub fn init_pipe(&mut self,stp:&Vec<u8>) -> Result<(), StepAnalysisError> {
+        let bodies: Vec<PipeBody> = analyze_stp_bodies(&stp)?;
+        //Load the main body, the others are offered in the body picker
+        let (main_body, body_warning) = select_main_body(&bodies)?;
+        self.set_body(&main_body)?;
+        self.body_warning = body_warning;
+        self.jobs.clear();
+        self.show_body_picker = bodies.len() > 1;
+        self.bodies = if (bodies.len() > 1) { bodies } else { vec![] };
+        self.source_path = None;
+        self.source_stp = Some(stp.clone());
+        Ok(())
+    }
+    pub fn queue_all_bodies(&mut self) -> Result<(), StepAnalysisError> {
+        self.jobs = self.bodies.iter().cloned().collect();
+        self.show_body_picker = false;
+        self.next_job()
+    }
+    pub fn next_job(&mut self) -> Result<(), StepAnalysisError> {
+        match self.jobs.pop_front() {
+            None => Ok(()),
+            Some(body) => self.set_body(&body),
+        }
+    }
+    pub fn set_body(&mut self, body: &PipeBody) -> Result<(), StepAnalysisError> {
+        if (body.segments.is_empty()) {
+            return Err(StepAnalysisError::EmptyProgram);
+        }
+        self.set_segments(body.segments.clone());
+        match body.wall_thickness() {
+            None => {
+                //Solid model, keep thickness entered by user
+                self.inner_diameter = None;
+            }
+            Some(t) => {
+                self.thickness = t;
+                self.inner_diameter = body.inner_radius.map(|r| r * 2.0);
+            }
+        }
+        Ok(())
+    }
+    //Common setup for every import source: STEP bodies, XYZ and CSV tables
+    pub fn set_segments(&mut self, lraclr_arr: Vec<LRACLR>) {
         let (tot_l,out_d)=LRACLR::total_len_out_d(&lraclr_arr);
-        let f=lraclr_arr.first().unwrap();
         self.len=tot_l;
-        self.diameter = f.pipe_radius*2.0;
+        match lraclr_arr.first() {
+            None => {}
+            Some(f) => { self.diameter = f.pipe_radius*2.0; }
+        }
         self.segments=lraclr_arr;
 
-        let mut bend_radiuses = HashSet::new();
-
-        self.segments.iter().for_each(|s|{
-            if(s.clr>0.0){
-                bend_radiuses.insert(OrderedFloat(s.clr));
-            }
-
-        });
-        let mut sorted_vec: Vec<_> = bend_radiuses.into_iter().collect();
-        sorted_vec.sort();
-
-        let count = sorted_vec.len();
-        match count {
-            1 => {
-                self.roller_a = sorted_vec[0].0;
-                self.roller_b = sorted_vec[0].0;
-                self.roller_c = sorted_vec[0].0;
-            }
-            2 => {
-                self.roller_a = sorted_vec[0].0;
-                self.roller_b = sorted_vec[1].0;
-                self.roller_c = sorted_vec[1].0;
-            }
-            3 => {
-                self.roller_a = sorted_vec[0].0;
-                self.roller_b = sorted_vec[1].0;
-                self.roller_c = sorted_vec[2].0;
-            }
-            _ => {
-                self.roller_a = sorted_vec[0].0;
-                self.roller_b = sorted_vec[0].0;
-                self.roller_c = sorted_vec[0].0;
-            }
+        self.assign_tooling();
+        self.import_error = None;
+        self.body_warning = None;
+    }
+    //Die stacks for the current segments, must be called after every edit of CLR or D
+    pub fn assign_tooling(&mut self) {
+        let (stacks, errors) = assign_stacks(&self.segments, &self.tooling, &self.machine.head);
+        errors.iter().for_each(|e| warn!("{}", e));
+        self.stacks = stacks;
+        self.tool_errors = errors;
+        self.collision = None;
+        self.collision_checked = false;
+        self.collision_task = None;
+        self.warnings_checked = false;
+    }
+    //Runs the whole program against the machine models of the asset folder on the compute pool,
+    //poll_collision_task picks up the result
+    pub fn check_collisions(&mut self, up_dir: &cgmath::Vector3<f64>) {
+        let assets = FileAssetReader::get_base_path().join("assets");
+        let stacks = self.stacks.clone();
+        let diameter = self.diameter;
+        let program = self.machine_program();
+        let machine = self.machine.clone();
+        let up_dir = *up_dir;
+        self.collision = None;
+        self.collision_checked = false;
+        self.collision_task = Some(AsyncComputeTaskPool::get().spawn(async move {
+            let model = MachineModel::load(&assets, &stacks, diameter)?;
+            Ok(find_collision(&program, &up_dir, &model, &machine, &CollisionSetup::default()))
+        }));
+    }
+    //Timed on the commanded feeds and angles, not the nominal part
+    pub fn cycle_time(&self) -> CycleTime {
+        cycle_time(&self.machine_program(), &self.machine, &self.stacks)
+    }
+    pub fn refresh_warnings(&mut self) {
+        if (!self.warnings_checked) {
+            self.warnings = validate_with_stacks(&self.machine_program(), &self.machine, &self.stacks);
+            self.warnings.extend(crossing_warnings(&self.segments));
+            self.warnings_checked = true;
+        }
+    }
+    pub fn init_xyz(&mut self, path: &PathBuf) -> Result<(), ImportError> {
+        let lraclr_arr = load_xyz(path, self.diameter / 2.0)?;
+        self.source_path = Some(path.clone());
+        self.source_stp = None;
+        self.bodies.clear();
+        self.jobs.clear();
+        self.inner_diameter = None;
+        self.set_segments(lraclr_arr);
+        Ok(())
+    }
+    pub fn machine_program(&self) -> Vec<LRACLR> {
+        machine_program(&self.segments, &self.springback, &self.elongation, &self.material_family())
+    }
+    pub fn material_family(&self) -> String {
+        self.materials.family(&self.material)
+    }
+    //Stock to cut from the bar, allowances included
+    pub fn cut_length(&self) -> f64 {
+        stock_length(&self.segments, &self.elongation, &self.material_family(), &self.allowances)
+    }
+    //Weight of the blank, zero for materials outside the catalog
+    pub fn weight(&self) -> f64 {
+        match self.materials.find(&self.material) {
+            None => 0.0,
+            Some(m) => m.pipe_weight(self.diameter, self.thickness, self.cut_length()),
+        }
+    }
+    pub fn job_info(&self) -> JobInfo {
+        JobInfo {
+            name: match &self.source_path {
+                None => "job".to_string(),
+                Some(p) => p.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or("job".to_string()),
+            },
+            material: self.material.clone(),
+            diameter: self.diameter,
+            thickness: self.thickness,
+            cut_length: self.cut_length(),
+            bends: self.deformation(),
+            loads: self.bend_loads(),
+        }
+    }
+    pub fn nest_part(&self, name: &str, program: &Vec<LRACLR>) -> NestPart {
+        NestPart::from_program(name, program, 1, &self.elongation, &self.material_family(), &self.allowances)
+    }
+    //Current part and every queued body, one piece each
+    pub fn add_nest_parts(&mut self, with_queue: bool) {
+        let mut parts: Vec<NestPart> = vec![self.nest_part(&self.job_info().name, &self.segments)];
+        if (with_queue) {
+            self.jobs.iter().enumerate().for_each(|(i, body)| {
+                parts.push(self.nest_part(&format!("job {}", i + 1), &body.segments));
+            });
+        }
+        self.nesting.parts.extend(parts);
+        self.nest_plan = None;
+    }
+    //Empty for materials outside the catalog
+    pub fn bend_loads(&self) -> Vec<BendLoad> {
+        match self.materials.find(&self.material) {
+            None => vec![],
+            Some(m) => bend_loads(&self.segments, self.thickness, m, &self.machine, &self.stacks),
+        }
+    }
+    pub fn deformation(&self) -> Vec<BendDeformation> {
+        program_deformation(&self.segments, self.thickness, self.materials.find(&self.material), &self.deformation_limits)
+    }
+    pub fn to_project(&self, up_dir: &cgmath::Vector3<f64>) -> ProjectFile {
+        ProjectFile {
+            version: PROJECT_VERSION,
+            segments: self.segments.clone(),
+            diameter: self.diameter,
+            thickness: self.thickness,
+            inner_diameter: self.inner_diameter,
+            material: self.material.clone(),
+            stacks: self.stacks.clone(),
+            deformation_limits: self.deformation_limits,
+            allowances: self.allowances,
+            cut_length: self.cut_length(),
+            up_dir: [up_dir.x, up_dir.y, up_dir.z],
+            machine: Some(self.machine.clone()),
+            source_path: self.source_path.as_ref().map(|p| p.to_string_lossy().to_string()),
+            source_step: self.source_stp.clone(),
         }
     }
+    //Segments are taken as saved, the embedded STEP is kept only as a reference
+    pub fn apply_project(&mut self, project: &ProjectFile) {
+        self.bodies.clear();
+        self.jobs.clear();
+        if let Some(m) = &project.machine {
+            self.machine = m.clone();
+        }
+        self.set_segments(project.segments.clone());
+        self.diameter = project.diameter;
+        self.thickness = project.thickness;
+        self.inner_diameter = project.inner_diameter;
+        if (!project.material.is_empty()) {
+            self.material = project.material.clone();
+        }
+        self.deformation_limits = project.deformation_limits;
+        self.allowances = project.allowances;
+        if (!project.stacks.is_empty()) {
+            self.stacks = project.stacks.clone();
+            self.tool_errors.clear();
+        }
+        self.warnings_checked = false;
+        self.source_path = project.source_path.as_ref().map(|p| PathBuf::from(p));
+        self.source_stp = project.source_step.clone();
+        self.material_changed = true;
+    }
+    pub fn init_csv(&mut self, path: &PathBuf) -> Result<(), ImportError> {
+        let lraclr_arr = load_csv(path, self.diameter / 2.0)?;
+        self.source_path = Some(path.clone());
+        self.source_stp = None;
+        self.bodies.clear();
+        self.jobs.clear();
+        self.inner_diameter = None;
+        self.set_segments(lraclr_arr);
+        Ok(())
+    }
 }
 
 #[derive(Component)]
@@ -122,7 +359,7 @@ impl Plugin for PipeViewPlugin {
         //app.add_systems(Startup, setup_pipe_system);
         //app.add_systems(Update, update_pipe_system);
         app.add_systems(Update,(
-            update_curved,update_straignt,
+            update_curved,update_straignt,poll_collision_task,
             //update_curved.run_if(in_state(RobotState::Feeding).or(in_state(RobotState::Rotating)).or(in_state(RobotState::Bending)).or(in_state(RobotState::ManualSetup))  ),
             //update_straignt.run_if(in_state(RobotState::Feeding).or(in_state(RobotState::Rotating)).or(in_state(RobotState::Bending)).or(in_state(RobotState::ManualSetup)) ),
 
@@ -130,6 +367,35 @@ impl Plugin for PipeViewPlugin {
     }
 }
 
+fn poll_collision_task(mut pipe_spec: ResMut<PipeSpecification>,
+                       mut machine_registers: ResMut<MachineRegisters>,
+                       mut next_state: ResMut<NextState<AppMode>>,
+) {
+    let result = match pipe_spec.collision_task.as_mut() {
+        Some(task) => match block_on(future::poll_once(task)) {
+            Some(result) => result,
+            None => return,
+        },
+        None => return,
+    };
+    pipe_spec.collision_task = None;
+    match result {
+        Ok(collision) => {
+            //Stop the simulation on the contact
+            if let Some(c) = &collision {
+                machine_registers.t = c.t as f32;
+                next_state.set(AppMode::Pause);
+            }
+            pipe_spec.collision = collision;
+            pipe_spec.collision_checked = true;
+        }
+        Err(e) => {
+            warn!("{}", e);
+            pipe_spec.import_error = Some(e.to_string());
+        }
+    }
+}
+
 fn update_curved( mut commands: Commands,
                   pipe_spec: Res<PipeSpecification>,
                   machine_staus: Res<MachineRegisters>,
@@ -186,7 +452,7 @@ fn update_straignt( mut commands: Commands,
 fn setup_pipe_system(
     mut commands: Commands,
     pipe_spec: Res<PipeSpecification>,
-    machine_staus: Res<MachineRegisters>,
+    mut machine_staus: ResMut<MachineRegisters>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     curr_app_state: Res<State<AppMode>>,
@@ -199,7 +465,10 @@ fn setup_pipe_system(
         return;
     }
 
-    let (meshes_t, meshes_m_t) = interpolate_by_t(&pipe_spec.segments, &machine_staus.up_dir);
+    if (!machine_staus.pipe_path.is_for(&pipe_spec.segments, &machine_staus.up_dir)) {
+        machine_staus.pipe_path = PipePath::new(&pipe_spec.segments, &machine_staus.up_dir);
+    }
+    let (meshes_t, meshes_m_t) = interpolate_by_t(&machine_staus.pipe_path, pipe_spec.segments[0].pipe_radius);
 
     let material_handle_gray = materials.add(StandardMaterial {
         base_color: Color::srgb(0.7, 0.7, 0.7),
@@ -2,8 +2,11 @@ use std::cmp::PartialEq;
 use bevy::prelude::*;
 use is_odd::IsOdd;
 use truck_base::cgmath64::Vector3;
-use crate::algo::cnc::{byt, LRACLR};
+use crate::algo::cnc::LRACLR;
+use crate::algo::path::PipePath;
 use crate::states::pipe_control::PipeSpecification;
+use crate::algo::kinematics::AxisRegisters;
+use crate::algo::tooling::{nearest_stack, stack_levels};
 use crate::states::scene_control::AppMode;
 use crate::ui::camera::cad_camera_controller;
 
@@ -32,6 +35,7 @@ pub struct MachineRegisters {
     pub dt: f32,
     pub playback_speed: f32,
     pub up_dir: cgmath::Vector3<f64>,
+    //Index of the die stack at the pipe axis
     pub bending_state: u32,
     pub current_bend_radius: f64,
     pub rot_angle: f64,
@@ -51,10 +55,12 @@ pub struct MachineRegisters {
     pub mov_pusher_x: f64,
     pub clamp: f64,
     pub robot_state: RobotState,
-
+    //Centerline of the loaded program, rebuilt when the program or up direction changes
+    pub pipe_path: PipePath,
 }
 impl Default for MachineRegisters {
     fn default() -> Self {
+        let axes = AxisRegisters::default();
         Self {
             current_id: 0,
             carriage_pos: 0.0,
@@ -72,22 +78,40 @@ impl Default for MachineRegisters {
             dx: 0.0,
             last_dx: 0.0,
             tm: Mat4::default(),
-            mov_y: 0.0,
-            mov_y_clamp: 0.0,
-            mov_z: 0.01,
-            mov_to_z: 0.01,
-            mov_static_y: -0.011,
-            mov_static_y_clamp: 0.0,
-            mov_static_x: 0.0,
-            mov_rotated_y: -0.068,
-            mov_rotated_y_clamp: 0.0,
-            mov_pusher_x: 0.0,
+            mov_y: axes.mov_y,
+            mov_y_clamp: axes.mov_y_clamp,
+            mov_z: axes.mov_z,
+            mov_to_z: axes.mov_z,
+            mov_static_y: axes.mov_static_y,
+            mov_static_y_clamp: axes.mov_static_y_clamp,
+            mov_static_x: axes.mov_static_x,
+            mov_rotated_y: axes.mov_rotated_y,
+            mov_rotated_y_clamp: axes.mov_rotated_y_clamp,
+            mov_pusher_x: axes.mov_pusher_x,
             clamp: 0.0,
             robot_state: RobotState::Idle,
+            pipe_path: PipePath::default(),
+        }
+    }
+}
+impl MachineRegisters {
+    //Axis positions of the kinematic chain shared with the collision check
+    pub fn axis_registers(&self) -> AxisRegisters {
+        AxisRegisters {
+            dx: self.dx,
+            bend_angle: self.bend_angle,
+            mov_z: self.mov_z,
+            mov_y: self.mov_y,
+            mov_y_clamp: self.mov_y_clamp,
+            mov_static_y: self.mov_static_y,
+            mov_static_y_clamp: self.mov_static_y_clamp,
+            mov_static_x: self.mov_static_x,
+            mov_rotated_y: self.mov_rotated_y,
+            mov_rotated_y_clamp: self.mov_rotated_y_clamp,
+            mov_pusher_x: self.mov_pusher_x,
         }
     }
 }
-
 
 pub struct MachineRegistersPlugin;
 
@@ -126,7 +150,8 @@ fn pause_tick(time: Res<Time>,
               curr_robot_state: Res<State<RobotState>>,
               mut next_robot_state: ResMut<NextState<RobotState>>,
 ) {
-    let (tm, dx, last_dx, is_rotated, curr_bend_angle, current_id) = calculate_pipe_matrix(&mut machine_registers, &pipe_spec.segments);
+    //Paused, the pipe holds its rotation
+    let (tm, dx, last_dx, is_rotated, curr_bend_angle, current_id) = calculate_pipe_matrix(&mut machine_registers, &pipe_spec.segments, 0.0);
     machine_registers.current_id = current_id;
     machine_registers.dx = dx;
     machine_registers.last_dx = last_dx;
@@ -141,10 +166,19 @@ fn tick(time: Res<Time>,
         curr_robot_state: Res<State<RobotState>>,
         mut next_robot_state: ResMut<NextState<RobotState>>,
 ) {
-    let speed = 0.05;
-    machine_registers.t += time.delta_secs() * speed;
+    //Carriage runs at the feed speed, while bending the arm draws the pipe with CLR*omega
+    let axes = &pipe_spec.machine.axes;
+    let v = if (curr_robot_state.get() == &RobotState::Bending && machine_registers.current_bend_radius > 0.0) {
+        axes.bend.v_max.to_radians() * machine_registers.current_bend_radius
+    } else {
+        axes.feed.v_max
+    };
+    if (pipe_spec.len > 0.0) {
+        machine_registers.t += time.delta_secs() * (v / pipe_spec.len) as f32;
+    }
 
-    let (tm, dx, last_dx, is_rotated, curr_bend_angle, current_id) = calculate_pipe_matrix(&mut machine_registers, &pipe_spec.segments);
+    let delta_rot = time.delta_secs_f64() * axes.rotation.v_max;
+    let (tm, dx, last_dx, is_rotated, curr_bend_angle, current_id) = calculate_pipe_matrix(&mut machine_registers, &pipe_spec.segments, delta_rot);
     machine_registers.current_id = current_id;
     if (is_rotated) {
         if (curr_robot_state.get() != &RobotState::Rotating) {
@@ -188,8 +222,10 @@ fn unclamping(time: Res<Time>,
     let mut is_clamp0_ok = false;
     let mut is_clamp1_ok = false;
     let mut is_clamp2_ok = false;
-    let ang_speed = time.delta_secs_f64() * 0.5;
-    let clamp_speed = time.delta_secs_f64() * 0.1;
+    let axes = &pipe_spec.machine.axes;
+    let ang_speed = time.delta_secs_f64() * axes.bend.v_max.to_radians();
+    let clamp_speed = time.delta_secs_f64() * axes.clamp.v_max / 1000.0;
+    let open = machine_registers.axis_registers().opened(pipe_spec.diameter);
 
 
     if (machine_registers.prev_bend_angle > 0.0) {
@@ -201,25 +237,25 @@ fn unclamping(time: Res<Time>,
         is_angle_ok = true;
     }
 
-    if (machine_registers.mov_y_clamp > -machine_registers.clamp * 2.0) {
+    if (machine_registers.mov_y_clamp > open.mov_y_clamp) {
         machine_registers.mov_y_clamp -= clamp_speed;
     } else {
-        machine_registers.mov_y_clamp = -machine_registers.clamp * 2.0;
+        machine_registers.mov_y_clamp = open.mov_y_clamp;
         is_clamp0_ok = true;
     }
 
 
-    if (machine_registers.mov_rotated_y_clamp < machine_registers.clamp * 3.0) {
+    if (machine_registers.mov_rotated_y_clamp < open.mov_rotated_y_clamp) {
         machine_registers.mov_rotated_y_clamp += clamp_speed;
     } else {
-        machine_registers.mov_rotated_y_clamp = machine_registers.clamp * 3.0;
+        machine_registers.mov_rotated_y_clamp = open.mov_rotated_y_clamp;
         is_clamp1_ok = true;
     }
 
-    if (machine_registers.mov_static_y_clamp < machine_registers.clamp * 3.0) {
+    if (machine_registers.mov_static_y_clamp < open.mov_static_y_clamp) {
         machine_registers.mov_static_y_clamp += clamp_speed;
     } else {
-        machine_registers.mov_static_y_clamp = machine_registers.clamp * 3.0;
+        machine_registers.mov_static_y_clamp = open.mov_static_y_clamp;
         is_clamp2_ok = true;
     }
 
@@ -236,7 +272,9 @@ fn clamping(time: Res<Time>,
             curr_robot_state: Res<State<RobotState>>,
             mut next_robot_state: ResMut<NextState<RobotState>>,
 ) {
-    let clamp_speed = time.delta_secs_f64() * 0.1;
+    let axes = &pipe_spec.machine.axes;
+    let clamp_speed = time.delta_secs_f64() * axes.clamp.v_max / 1000.0;
+    let z_speed = time.delta_secs_f64() * axes.stack_change.v_max / 1000.0;
 
     let mut is_z_ok = false;
 
@@ -249,7 +287,7 @@ fn clamping(time: Res<Time>,
         is_z_ok = true;
     } else {
         if (machine_registers.mov_z < machine_registers.mov_to_z) {
-            machine_registers.mov_z += clamp_speed;
+            machine_registers.mov_z += z_speed;
             if (machine_registers.mov_z > machine_registers.mov_to_z) {
                 machine_registers.mov_z = machine_registers.mov_to_z;
                 is_z_ok = true;
@@ -257,7 +295,7 @@ fn clamping(time: Res<Time>,
         }
 
         if (machine_registers.mov_z > machine_registers.mov_to_z) {
-            machine_registers.mov_z -= clamp_speed;
+            machine_registers.mov_z -= z_speed;
             if (machine_registers.mov_z < machine_registers.mov_to_z) {
                 machine_registers.mov_z = machine_registers.mov_to_z;
                 is_z_ok = true;
@@ -301,12 +339,17 @@ pub fn on_pipe_loaded(mut machine_registers: ResMut<MachineRegisters>, pipe_spec
 }
 pub fn on_pipe_loading(mut machine_registers: ResMut<MachineRegisters>, pipe_spec: Res<PipeSpecification>, mut next_robot_state: ResMut<NextState<RobotState>>) {
     machine_registers.robot_state = RobotState::PipeLoading;
-    machine_registers.mov_pusher_x = 3.521 - pipe_spec.len / 1000.0;
+    let loaded = AxisRegisters::loaded(&pipe_spec.machine, pipe_spec.len, pipe_spec.diameter);
+    machine_registers.mov_pusher_x = loaded.mov_pusher_x;
     machine_registers.clamp = pipe_spec.diameter / 1000.0;
-    machine_registers.mov_static_y_clamp = machine_registers.clamp * 3.0;
-    machine_registers.mov_rotated_y_clamp = machine_registers.clamp * 3.0;
-    machine_registers.mov_y_clamp = -machine_registers.clamp * 2.0;
-    machine_registers.mov_y = 0.199 - pipe_spec.diameter / 1000.0;
+    machine_registers.mov_static_y_clamp = loaded.mov_static_y_clamp;
+    machine_registers.mov_rotated_y_clamp = loaded.mov_rotated_y_clamp;
+    machine_registers.mov_y_clamp = loaded.mov_y_clamp;
+    machine_registers.mov_y = loaded.mov_y;
+    //Head starts on the top stack
+    machine_registers.bending_state = 0;
+    machine_registers.mov_z = loaded.mov_z;
+    machine_registers.mov_to_z = machine_registers.mov_z;
     next_robot_state.set(RobotState::PipeLoaded);
 }
 fn on_idle(mut machine_registers: ResMut<MachineRegisters>) {
@@ -334,8 +377,9 @@ fn on_pipe_unclamping(mut machine_registers: ResMut<MachineRegisters>, pipe_spec
     println!("on_pipe_unclamping {:?}  {:?}", machine_registers.bend_angle, machine_registers.prev_bend_angle);
 }
 
-fn rotate_pipe(mut machine_registers: ResMut<MachineRegisters>, pipe_spec: Res<PipeSpecification>, mut next_robot_state: ResMut<NextState<RobotState>>) {
-    let (tm, dx, last_dx, is_rotated, curr_bend_angle, current_id) = calculate_pipe_matrix(&mut machine_registers, &pipe_spec.segments);
+fn rotate_pipe(time: Res<Time>, mut machine_registers: ResMut<MachineRegisters>, pipe_spec: Res<PipeSpecification>, mut next_robot_state: ResMut<NextState<RobotState>>) {
+    let delta_rot = time.delta_secs_f64() * pipe_spec.machine.axes.rotation.v_max;
+    let (tm, dx, last_dx, is_rotated, curr_bend_angle, current_id) = calculate_pipe_matrix(&mut machine_registers, &pipe_spec.segments, delta_rot);
 
     if (is_rotated) {
         machine_registers.tm = tm;
@@ -346,11 +390,16 @@ fn rotate_pipe(mut machine_registers: ResMut<MachineRegisters>, pipe_spec: Res<P
 }
 
 
-fn calculate_pipe_matrix(machine_staus: &mut ResMut<MachineRegisters>, lraclr_arr: &Vec<LRACLR>) -> (Mat4, f64, f64, bool, f64, u64) {
+//delta_rot is the rotation axis travel of this frame in degrees
+fn calculate_pipe_matrix(machine_staus: &mut ResMut<MachineRegisters>, lraclr_arr: &Vec<LRACLR>, delta_rot: f64) -> (Mat4, f64, f64, bool, f64, u64) {
     let t = machine_staus.t as f64;
-    let delta_rot: f64 = 1.0;
 
-    let (pt, xv, yv, zv, rot_deg, id, cp, l, theta, bend_radius) = byt(t, lraclr_arr, &machine_staus.up_dir);
+    if (!machine_staus.pipe_path.is_for(lraclr_arr, &machine_staus.up_dir)) {
+        machine_staus.pipe_path = PipePath::new(lraclr_arr, &machine_staus.up_dir);
+    }
+    let sample = machine_staus.pipe_path.at(t);
+    let l = machine_staus.pipe_path.len();
+    let (rot_deg, id, theta, bend_radius) = (sample.rot_deg, sample.id, sample.theta, sample.bend_radius);
     let current_id = id;
     machine_staus.current_bend_radius = bend_radius;
     let dx = t * l;
@@ -376,61 +425,20 @@ fn calculate_pipe_matrix(machine_staus: &mut ResMut<MachineRegisters>, lraclr_ar
         //bend_commands.is_paused=true;
     }
 
-    let mut x_rotation = Mat3::from_cols(
-        Vec3::new(1.0, 0.0, 0.0),
-        Vec3::new(0.0, machine_staus.rot_step.to_radians().cos() as f32, -machine_staus.rot_step.to_radians().sin() as f32),
-        Vec3::new(0.0, machine_staus.rot_step.to_radians().sin() as f32, machine_staus.rot_step.to_radians().cos() as f32),
-    );
-
-    let dest_pos = -Vec3::new(pt.x as f32, pt.y as f32, pt.z as f32);
-    let source_x = Vec3::new(xv.x as f32, xv.y as f32, xv.z as f32);
-    let source_y = Vec3::new(yv.x as f32, yv.y as f32, yv.z as f32);
-    let source_z = Vec3::new(zv.x as f32, zv.y as f32, zv.z as f32);
-
-    let dest_x = Vec3::X;  // X-axis now points where Y used to
-    let dest_y = Vec3::Y; // Y-axis now points where -X used to
-    let dest_z = Vec3::Z;  // Z-axis is unchanged
-
-    let m_source = Mat3::from_cols(source_x, source_y, source_z);
-    let m_dest = Mat3::from_cols(dest_x, dest_y, dest_z);
-
-    let z_mirror = Mat3::from_cols(
-        Vec3::new(-1.0, 0.0, 0.0),
-        Vec3::new(0.0, -1.0, 0.0),
-        Vec3::new(0.0, 0.0, 1.0),
-    );
-
-    let x_mirror = Mat3::from_cols(
-        Vec3::new(1.0, 0.0, 0.0),
-        Vec3::new(0.0, -1.0, 0.0),
-        Vec3::new(0.0, 0.0, -1.0),
-    );
-    let rot_matrix: Mat3 = x_rotation * x_mirror * z_mirror * m_dest * m_source.transpose();
-    let final_transform_matrix: Mat4 = Mat4::from_mat3(rot_matrix) * Mat4::from_translation(dest_pos);
+    let final_transform_matrix: Mat4 = sample.machine_matrix(machine_staus.rot_step);
 
     (final_transform_matrix, dx, last_dx, is_paused, theta, current_id)
 }
 
 fn calculate_dz(mr: &MachineRegisters, pipe_spec: &PipeSpecification) -> (f64, i32) {
-    let mut dz: f64 = 0.0;
-    let mut state = 0;
-    match mr.bending_state {
-        0 => {
-            let roller = pipe_spec.roller_a;
-            if (roller != mr.current_bend_radius) {
-                state = 1;
-                dz = 0.053;
-            };
-        }
-        1 => {
-            let roller = pipe_spec.roller_b;
-            if (roller != mr.current_bend_radius) {
-                state = 0;
-                dz = -0.053;
-            };
+    let current = mr.bending_state as usize;
+    match nearest_stack(&pipe_spec.stacks, current, mr.current_bend_radius, pipe_spec.diameter) {
+        Some(next) if next != current => {
+            let levels = stack_levels(&pipe_spec.stacks);
+            //Head moves up to bring a lower stack to the pipe axis
+            let dz = (levels[next] - levels[current]) / 1000.0;
+            (dz, next as i32)
         }
-        _ => {}
+        _ => (0.0, current as i32),
     }
-
-    (dz, state)
-}
\ No newline at end of file
+}
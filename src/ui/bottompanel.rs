@@ -1,6 +1,8 @@
 use bevy::app::{App, Plugin};
 use bevy::prelude::*; // Res, ResMut, State и т.д.
 use bevy_egui::{egui, EguiContexts, EguiPrimaryContextPass};
+use crate::algo::tooling::stack_changes;
+use crate::states::pipe_control::PipeSpecification;
 use crate::states::scene_control::AppMode;
 use crate::ui::UiOrder;
 use crate::states::state_machine::{MachineRegisters, RobotState};
@@ -17,6 +19,7 @@ impl Plugin for BottomUiPanelPlugin {
 fn ui_system(
     mut contexts: EguiContexts,
     mut status: ResMut<MachineRegisters>, 
+    pipe_spec: Res<PipeSpecification>,
     mut next_state: ResMut<NextState<AppMode>>, 
     current_state: Res<State<AppMode>>, 
     curr_robot_state: Res<State<RobotState>>,
@@ -54,13 +57,34 @@ fn ui_system(
                 status.t = 0.0; // Сброс в начало
             }
             
+            if pipe_spec.stacks.len() > 1 {
+                ui.label(format!("Stack {}/{}", status.bending_state + 1, pipe_spec.stacks.len()));
+            }
+
             ui.style_mut().spacing.slider_width = ui.available_width() - 50.0;
+            let slider_width = ui.style().spacing.slider_width;
 
             let slider = ui.add(egui::Slider::new(&mut status.t, 0.0..=1.0));
 
             if slider.dragged() {
                 next_state.set(AppMode::Pause);
             }
+
+            //Stack change moves as marks on the timeline
+            if pipe_spec.len > 0.0 {
+                let rail_pad = slider.rect.height() / 2.0;
+                let rail_left = slider.rect.left() + rail_pad;
+                let rail_width = slider_width - rail_pad * 2.0;
+                let painter = ui.painter().clone();
+                stack_changes(&pipe_spec.segments, &pipe_spec.stacks).iter().for_each(|change| {
+                    let x = rail_left + rail_width * (change.dist / pipe_spec.len) as f32;
+                    let color = egui::Color32::from_rgb(255, 165, 0);
+                    let mark = egui::Rect::from_min_max(egui::pos2(x - 2.0, slider.rect.top()), egui::pos2(x + 2.0, slider.rect.bottom()));
+                    painter.rect_filled(mark, 0.0, color);
+                    ui.interact(mark, ui.id().with(("stack_change", change.row)), egui::Sense::hover())
+                        .on_hover_text(format!("Row {}: stack {} -> {}, Z {:+.1} mm", change.row, change.from + 1, change.to + 1, change.dz));
+                });
+            }
         });
     });
 }
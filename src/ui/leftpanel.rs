@@ -1,10 +1,12 @@
 use std::f64::consts::PI;
+use itertools::Itertools;
 use bevy::app::{App, Plugin};
 use bevy_ecs::change_detection::ResMut;
 use bevy_ecs::prelude::IntoScheduleConfigs;
 use bevy_egui::{egui, EguiContexts, EguiPrimaryContextPass};
 use bevy_egui::egui::Color32;
-use crate::algo::cnc::{add_lra_row, delete_lra_row, LRACLR};
+use crate::algo::cnc::{add_lra_row, delete_lra_row, CycleOpKind, LoadFlag, LRACLR};
+use crate::algo::deformation::DeformationFlag;
 
 use crate::ui::UiOrder;
 use bevy::prelude::*;
@@ -48,10 +50,92 @@ fn ui_system(
                 let color = egui::Color32::from_rgb(255, 255, 255);
                 let L = format!("Total len {} mm. ", total_length);
                 ui.label(egui::RichText::new(L).color(color));
+                let cut_len = pipe_spec.cut_length();
+                ui.label(egui::RichText::new(format!("Cut len {:.1} mm. ", cut_len)).color(color));
+                ui.label(egui::RichText::new(format!("Weight {:.2} kg. ", pipe_spec.weight())).color(color));
+                let cycle = pipe_spec.cycle_time();
+                let breakdown = [CycleOpKind::Feed, CycleOpKind::Rotate, CycleOpKind::StackChange, CycleOpKind::Clamp, CycleOpKind::Bend, CycleOpKind::Release].iter()
+                    .map(|kind| format!("{}: {:.1} s", kind, cycle.time_of(*kind)))
+                    .join("\n");
+                ui.label(egui::RichText::new(format!("Cycle {:.1} s. ", cycle.total)).color(color)).on_hover_text(breakdown);
                 ui.label(egui::RichText::new("Pipe D= ").color(color));
                 let radius_labl = ui.add(egui::TextEdit::singleline(&mut pipe_diameter).text_color(color));
             });
 
+            ui.horizontal(|ui| {
+                let color = egui::Color32::from_rgb(255, 255, 255);
+                ui.label(egui::RichText::new("Wall S= ").color(color));
+                let max_wall = pipe_spec.diameter / 2.0;
+                if (ui.add(egui::DragValue::new(&mut pipe_spec.thickness).speed(0.1).range(0.1..=max_wall)).changed()) {
+                    pipe_spec.inner_diameter = None;
+                }
+                let inner_d = match pipe_spec.inner_diameter {
+                    None => pipe_spec.diameter - pipe_spec.thickness * 2.0,
+                    Some(d) => d,
+                };
+                let source = if pipe_spec.inner_diameter.is_some() { "from model" } else { "manual" };
+                ui.label(egui::RichText::new(format!("ID= {:.2} mm ({})", inner_d, source)).color(color));
+            });
+
+            ui.horizontal(|ui| {
+                let color = egui::Color32::from_rgb(255, 255, 255);
+                ui.label(egui::RichText::new("Grip front ").color(color));
+                ui.add(egui::DragValue::new(&mut pipe_spec.allowances.front_grip).speed(1.0).range(0.0..=1000.0));
+                ui.label(egui::RichText::new("rear ").color(color));
+                ui.add(egui::DragValue::new(&mut pipe_spec.allowances.rear_grip).speed(1.0).range(0.0..=1000.0));
+                ui.label(egui::RichText::new("Trim ").color(color));
+                ui.add(egui::DragValue::new(&mut pipe_spec.allowances.end_trim).speed(0.5).range(0.0..=500.0));
+            });
+
+            ui.horizontal(|ui| {
+                let color = egui::Color32::from_rgb(255, 255, 255);
+                ui.label(egui::RichText::new("Material ").color(color));
+                let mut material = pipe_spec.material.clone();
+                egui::ComboBox::from_id_salt("material").selected_text(material.as_str()).show_ui(ui, |ui| {
+                    pipe_spec.materials.materials.iter().for_each(|m| {
+                        ui.selectable_value(&mut material, m.name.clone(), m.name.as_str())
+                            .on_hover_text(format!("{}\nRp0.2 {:.0} MPa\nRm {:.0} MPa\nA {:.0} %\nE {:.0} GPa\n{:.0} kg/m3",
+                                                   m.family, m.yield_strength, m.uts, m.elongation, m.e_modulus, m.density));
+                    });
+                });
+                if (material != pipe_spec.material) {
+                    pipe_spec.material = material;
+                    pipe_spec.material_changed = true;
+                    pipe_spec.warnings_checked = false;
+                }
+            });
+            if let Some(w) = &pipe_spec.body_warning {
+                ui.label(egui::RichText::new(w.to_string()).color(Color32::YELLOW));
+            }
+            pipe_spec.tool_errors.iter().for_each(|e| {
+                ui.label(egui::RichText::new(e.to_string()).color(Color32::RED));
+            });
+            pipe_spec.refresh_warnings();
+            let warnings = pipe_spec.warnings.clone();
+            warnings.iter().filter(|w| w.rows().is_empty()).for_each(|w| {
+                ui.label(egui::RichText::new(w.to_string()).color(Color32::RED));
+            });
+            if (pipe_spec.collision_task.is_some()) {
+                ui.label(egui::RichText::new("Checking collisions...").color(Color32::YELLOW));
+            } else if (pipe_spec.collision_checked) {
+                match &pipe_spec.collision {
+                    Some(c) => { ui.label(egui::RichText::new(format!("Collision: {}", c)).color(Color32::RED)); }
+                    None => { ui.label(egui::RichText::new("No collisions").color(Color32::GREEN)); }
+                }
+            }
+            ui.horizontal(|ui| {
+                let color = egui::Color32::from_rgb(255, 255, 255);
+                ui.label(egui::RichText::new("Max thin % ").color(color));
+                ui.add(egui::DragValue::new(&mut pipe_spec.deformation_limits.max_thinning).speed(0.5).range(0.0..=100.0));
+                ui.label(egui::RichText::new("thick % ").color(color));
+                ui.add(egui::DragValue::new(&mut pipe_spec.deformation_limits.max_thickening).speed(0.5).range(0.0..=100.0));
+                ui.label(egui::RichText::new("oval % ").color(color));
+                ui.add(egui::DragValue::new(&mut pipe_spec.deformation_limits.max_ovality).speed(0.5).range(0.0..=100.0));
+            });
+            let deformation = pipe_spec.deformation();
+            let loads = pipe_spec.bend_loads();
+            let machine_lra = pipe_spec.machine_program();
+
             ui.horizontal(|ui| {
                 let color = egui::Color32::from_rgb(255, 255, 255);
                 ui.add_sized([col_width, col_heigth],
@@ -70,6 +154,34 @@ fn ui_system(
                              egui::Label::new(egui::RichText::new("Bend R").color(color)),
                 );
                 ui.separator();
+                ui.add_sized([col_width, col_heigth],
+                             egui::Label::new(egui::RichText::new("L cmd").color(color)),
+                );
+                ui.separator();
+                ui.add_sized([col_width, col_heigth],
+                             egui::Label::new(egui::RichText::new("A cmd").color(color)),
+                );
+                ui.separator();
+                ui.add_sized([col_width, col_heigth],
+                             egui::Label::new(egui::RichText::new("Die R").color(color)),
+                );
+                ui.separator();
+                ui.add_sized([col_width, col_heigth],
+                             egui::Label::new(egui::RichText::new("Thin %").color(color)),
+                );
+                ui.separator();
+                ui.add_sized([col_width, col_heigth],
+                             egui::Label::new(egui::RichText::new("Oval %").color(color)),
+                );
+                ui.separator();
+                ui.add_sized([col_width, col_heigth],
+                             egui::Label::new(egui::RichText::new("M kNm").color(color)),
+                );
+                ui.separator();
+                ui.add_sized([col_width, col_heigth],
+                             egui::Label::new(egui::RichText::new("Clamp kN").color(color)),
+                );
+                ui.separator();
             });
             ui.separator();
 
@@ -147,6 +259,57 @@ fn ui_system(
 
                     ui.separator();
 
+                    let l_cmd = match machine_lra.get(counter as usize) {
+                        Some(m) => format!("{:.2}", m.l),
+                        None => "".to_string(),
+                    };
+                    ui.add_sized([col_width, ui.available_height()], egui::Label::new(egui::RichText::new(l_cmd).color(color_white)));
+                    ui.separator();
+                    let (a_cmd, clr_cmd) = match machine_lra.get(counter as usize) {
+                        Some(m) if m.clr > 0.0 => (format!("{:.2}", m.a), format!("{:.2}", m.clr)),
+                        _ => ("".to_string(), "".to_string()),
+                    };
+                    ui.add_sized([col_width, ui.available_height()], egui::Label::new(egui::RichText::new(a_cmd).color(color_white)));
+                    ui.separator();
+                    ui.add_sized([col_width, ui.available_height()], egui::Label::new(egui::RichText::new(clr_cmd).color(color_white)));
+                    ui.separator();
+
+                    let bend = deformation.iter().find(|b| b.row == counter as usize);
+                    let (thin, thin_color, oval, oval_color) = match bend {
+                        None => ("".to_string(), color_white, "".to_string(), color_white),
+                        Some(b) => {
+                            let thin_color = if (b.is_flagged(DeformationFlag::Thinning) || b.is_flagged(DeformationFlag::Thickening) || b.is_flagged(DeformationFlag::Strain)) { Color32::RED } else { color_white };
+                            let oval_color = if (b.is_flagged(DeformationFlag::Ovality)) { Color32::RED } else { color_white };
+                            (format!("{:.1}", b.thinning), thin_color, format!("{:.1}", b.ovality), oval_color)
+                        }
+                    };
+                    ui.add_sized([col_width, ui.available_height()], egui::Label::new(egui::RichText::new(thin).color(thin_color)))
+                        .on_hover_text(match bend {
+                            None => "".to_string(),
+                            Some(b) => format!("Outer wall {:.2} mm\nInner wall {:.2} mm (+{:.1} %)\nOuter strain {:.1} %", b.outer_wall, b.inner_wall, b.thickening, b.outer_strain),
+                        });
+                    ui.separator();
+                    ui.add_sized([col_width, ui.available_height()], egui::Label::new(egui::RichText::new(oval).color(oval_color)));
+                    ui.separator();
+
+                    let load = loads.iter().find(|b| b.row == counter as usize);
+                    let (moment, moment_color, clamp_force, clamp_color) = match load {
+                        None => ("".to_string(), color_white, "".to_string(), color_white),
+                        Some(b) => {
+                            let moment_color = if (b.is_flagged(LoadFlag::Torque)) { Color32::RED } else { color_white };
+                            let clamp_color = if (b.is_flagged(LoadFlag::Clamp) || b.is_flagged(LoadFlag::PressureDie)) { Color32::RED } else { color_white };
+                            (format!("{:.2}", b.moment), moment_color, format!("{:.1}", b.clamp_force), clamp_color)
+                        }
+                    };
+                    ui.add_sized([col_width, ui.available_height()], egui::Label::new(egui::RichText::new(moment).color(moment_color)));
+                    ui.separator();
+                    ui.add_sized([col_width, ui.available_height()], egui::Label::new(egui::RichText::new(clamp_force).color(clamp_color)))
+                        .on_hover_text(match load {
+                            None => "".to_string(),
+                            Some(b) => format!("Pressure die {:.1} kN", b.pressure_die_force),
+                        });
+                    ui.separator();
+
                     let is_add_button_disabled = if (counter != last_index) { true } else { false };
                     let add_button = ui.add_enabled(is_add_button_disabled, egui::Button::new("+"));
                     if (add_button.clicked()) {
@@ -161,6 +324,15 @@ fn ui_system(
                         deleted_index = counter;
                         is_changed = true;
                     }
+
+                    let row_warnings: Vec<String> = warnings.iter()
+                        .filter(|w| w.rows().contains(&(counter as usize)))
+                        .map(|w| w.to_string())
+                        .collect();
+                    if (!row_warnings.is_empty()) {
+                        ui.label(egui::RichText::new(egui_material_icons::icons::ICON_WARNING).color(Color32::from_rgb(255, 165, 0)))
+                            .on_hover_text(row_warnings.join("\n"));
+                    }
                 });
 
                 counter = counter + 1;
@@ -177,6 +349,7 @@ fn ui_system(
             }
 
             if(is_changed){
+                pipe_spec.assign_tooling();
                 next_state.set( AppMode::Restarting);
             }
         });
@@ -5,7 +5,14 @@ use bevy_ecs::change_detection::{Res, ResMut};
 use bevy_ecs::prelude::IntoScheduleConfigs;
 use bevy_egui::{egui, EguiContexts, EguiPrimaryContextPass};
 use rfd::FileDialog;
-use crate::algo::cnc::{reverse_lraclr, save_csv};
+use crate::algo::compensation::{load_elongation, load_springback};
+use crate::algo::post::{builtin_posts, export_program, load_template, PostProcessor};
+use crate::algo::project::{load_project, save_project};
+use crate::algo::material::{load_materials, save_materials, MATERIAL_FILE};
+use crate::algo::nesting::{nest_parts, save_nest_plan, StockBar};
+use crate::algo::machine::{load_machine, save_machine, MACHINE_FILE};
+use crate::algo::tooling::{load_tooling, save_tooling, TOOLING_FILE};
+use crate::algo::cnc::{lraclr_to_xyz, reverse_lraclr, save_csv, save_xyz_csv, save_xyz_json};
 use crate::states::pipe_control::PipeSpecification;
 use crate::states::scene_control::AppMode;
 use crate::states::state_machine::{MachineRegisters, RobotState};
@@ -51,14 +58,156 @@ fn ui_system(
         ui.horizontal_wrapped(|ui| {
             if ui.button("File").clicked() {
               if let Some(path) = rfd::FileDialog::new().add_filter("STEP", &["stp", "step"]).pick_file() {
-                        match fs::read(path) {
+                        match fs::read(&path) {
                             Ok(stp) => {
-                                pipe_spec.init_pipe(&stp);
+                                match pipe_spec.init_pipe(&stp) {
+                                    Ok(_) => {
+                                        pipe_spec.source_path = Some(path.clone());
+                                        next_state.set(AppMode::Restarting);
+                                    }
+                                    Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                                }
+                            }
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+            };
+            ui.separator();
+            ui.menu_button("Project", |ui| {
+                if ui.button("Open").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Project", &["json"]).pick_file() {
+                        match load_project(&path) {
+                            Ok(project) => {
+                                pipe_spec.apply_project(&project);
+                                machine_registers.up_dir = cgmath::Vector3::new(project.up_dir[0], project.up_dir[1], project.up_dir[2]);
+                                next_state.set(AppMode::Restarting);
+                            }
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+                if ui.button("Save").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Project", &["json"]).save_file() {
+                        let project = pipe_spec.to_project(&machine_registers.up_dir);
+                        match save_project(&project, &path) {
+                            Ok(_) => {}
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+            });
+            ui.separator();
+            ui.menu_button("Compensation", |ui| {
+                if ui.button("Springback").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Springback", &["json"]).pick_file() {
+                        match load_springback(&path) {
+                            Ok(table) => {
+                                pipe_spec.springback = table;
+                                pipe_spec.warnings_checked = false;
+                            }
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+                if ui.button("Elongation").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Elongation", &["json"]).pick_file() {
+                        match load_elongation(&path) {
+                            Ok(table) => {
+                                pipe_spec.elongation = table;
+                                pipe_spec.warnings_checked = false;
+                            }
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+            });
+            ui.menu_button("Materials", |ui| {
+                if ui.button("Open").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Materials", &["json"]).pick_file() {
+                        match load_materials(&path) {
+                            Ok(catalog) => {
+                                pipe_spec.materials = catalog;
+                                pipe_spec.material_changed = true;
+                                pipe_spec.warnings_checked = false;
+                            }
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+                if ui.button("Save").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Materials", &["json"]).set_file_name(MATERIAL_FILE).save_file() {
+                        if let Err(e) = save_materials(&pipe_spec.materials, &path) {
+                            pipe_spec.import_error = Some(format!("{:?}: {}", path, e));
+                        }
+                    }
+                }
+            });
+            ui.menu_button("Tooling", |ui| {
+                if ui.button("Open").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Tooling", &["json"]).pick_file() {
+                        match load_tooling(&path) {
+                            Ok(tooling) => {
+                                pipe_spec.tooling = tooling;
+                                pipe_spec.assign_tooling();
+                                next_state.set(AppMode::Restarting);
+                            }
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+                if ui.button("Save").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Tooling", &["json"]).set_file_name(TOOLING_FILE).save_file() {
+                        if let Err(e) = save_tooling(&pipe_spec.tooling, &path) {
+                            pipe_spec.import_error = Some(format!("{:?}: {}", path, e));
+                        }
+                    }
+                }
+            });
+            ui.menu_button("Machine", |ui| {
+                if ui.button("Open").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Machine", &["json"]).pick_file() {
+                        match load_machine(&path) {
+                            Ok(machine) => {
+                                pipe_spec.machine = machine;
+                                pipe_spec.assign_tooling();
                                 next_state.set(AppMode::Restarting);
                             }
-                            Err(_) => {}
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
                         }
                     }
+                }
+                if ui.button("Save").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("Machine", &["json"]).set_file_name(MACHINE_FILE).save_file() {
+                        if let Err(e) = save_machine(&pipe_spec.machine, &path) {
+                            pipe_spec.import_error = Some(format!("{:?}: {}", path, e));
+                        }
+                    }
+                }
+                ui.separator();
+                if ui.button("Check collisions").clicked() {
+                    ui.close();
+                    let up_dir = machine_registers.up_dir;
+                    pipe_spec.check_collisions(&up_dir);
+                }
+            });
+            ui.separator();
+            if ui.button("XYZ").clicked() {
+                if let Some(path) = rfd::FileDialog::new().add_filter("XYZ", &["csv", "txt", "xyz"]).pick_file() {
+                    match pipe_spec.init_xyz(&path) {
+                        Ok(_) => { next_state.set(AppMode::Restarting); }
+                        Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                    }
+                }
             };
             ui.separator();
             if ui.button("Reverse").clicked() {
@@ -68,13 +217,75 @@ fn ui_system(
             };
 
             ui.separator();
-            if ui.button("CSV").clicked() {
-                if let Some(path) = FileDialog::new().add_filter("CSV", &["csv"]).set_directory("/").save_file() {
-                    save_csv(& pipe_spec.segments, &path);
+            ui.menu_button("CSV", |ui| {
+                if ui.button("Open").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("CSV", &["csv", "txt"]).pick_file() {
+                        match pipe_spec.init_csv(&path) {
+                            Ok(_) => { next_state.set(AppMode::Restarting); }
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
                 }
-
-
-            }
+                if ui.button("Save").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("CSV", &["csv"]).set_directory("/").save_file() {
+                        if let Err(e) = save_csv(&pipe_spec.segments, &path) {
+                            pipe_spec.import_error = Some(format!("{:?}: {}", path, e));
+                        }
+                    }
+                }
+            });
+            ui.separator();
+            ui.menu_button("Program", |ui| {
+                let mut post: Option<Box<dyn PostProcessor>> = None;
+                builtin_posts().into_iter().for_each(|p| {
+                    if ui.button(p.name()).clicked() {
+                        post = Some(p);
+                    }
+                });
+                if ui.button("Template...").clicked() {
+                    if let Some(path) = FileDialog::new().add_filter("Template", &["txt", "tpl"]).pick_file() {
+                        match load_template(&path) {
+                            Ok(t) => { post = Some(Box::new(t)); }
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+                if let Some(post) = post {
+                    ui.close();
+                    let ext = post.extension();
+                    if let Some(path) = FileDialog::new().add_filter(post.name().as_str(), &[ext.as_str()]).save_file() {
+                        match export_program(post.as_ref(), &pipe_spec.machine_program(), &pipe_spec.job_info(), &path) {
+                            Ok(_) => {}
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+            });
+            ui.separator();
+            ui.menu_button("XYZ out", |ui| {
+                if ui.button("CSV").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("CSV", &["csv"]).save_file() {
+                        let pts = lraclr_to_xyz(&pipe_spec.segments, &machine_registers.up_dir);
+                        match save_xyz_csv(&pts, &path) {
+                            Ok(_) => {}
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+                if ui.button("JSON").clicked() {
+                    ui.close();
+                    if let Some(path) = FileDialog::new().add_filter("JSON", &["json"]).save_file() {
+                        let pts = lraclr_to_xyz(&pipe_spec.segments, &machine_registers.up_dir);
+                        match save_xyz_json(&pts, &path) {
+                            Ok(_) => {}
+                            Err(e) => { pipe_spec.import_error = Some(format!("{:?}: {}", path, e)); }
+                        }
+                    }
+                }
+            });
             ui.separator();
 
             if ui.button("STP").clicked() {
@@ -160,11 +371,32 @@ fn ui_system(
 
                 if(stp_indx!=50){
                     let stp=pipe_spec.demos[stp_indx].clone();
-                    pipe_spec.init_pipe(&stp);
-                    next_state.set(AppMode::Restarting);
+                    match pipe_spec.init_pipe(&stp) {
+                        Ok(_) => { next_state.set(AppMode::Restarting); }
+                        Err(e) => { pipe_spec.import_error = Some(format!("Demo{}: {}", stp_indx + 1, e)); }
+                    }
                 }
                });
             ui.separator();
+            if (!pipe_spec.bodies.is_empty()) {
+                if ui.button(format!("Bodies ({})", pipe_spec.bodies.len())).clicked() {
+                    pipe_spec.show_body_picker = true;
+                }
+                ui.separator();
+            }
+            if (!pipe_spec.jobs.is_empty()) {
+                if ui.button(format!("Next job ({})", pipe_spec.jobs.len())).clicked() {
+                    match pipe_spec.next_job() {
+                        Ok(_) => { next_state.set(AppMode::Restarting); }
+                        Err(e) => { pipe_spec.import_error = Some(e.to_string()); }
+                    }
+                }
+                ui.separator();
+            }
+            if ui.button("Nesting").clicked() {
+                pipe_spec.show_nesting = !pipe_spec.show_nesting;
+            }
+            ui.separator();
             if ui.button(eye_icon).clicked() {
 
             }
@@ -177,4 +409,197 @@ fn ui_system(
         });
     });
 
+    let mut selected_body: Option<usize> = None;
+    let mut queue_all = false;
+    let mut close_picker = false;
+    if (pipe_spec.show_body_picker) {
+        egui::Window::new("Pipe bodies")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                egui::Grid::new("bodies_grid").striped(true).show(ui, |ui| {
+                    ui.label("#");
+                    ui.label("Pipe D");
+                    ui.label("Total len");
+                    ui.label("Bends");
+                    ui.label("");
+                    ui.end_row();
+                    pipe_spec.bodies.iter().enumerate().for_each(|(i, body)| {
+                        ui.label((i + 1).to_string());
+                        ui.label(format!("{:.2}", body.outer_radius * 2.0));
+                        ui.label(format!("{:.1}", body.total_len()));
+                        ui.label(body.bend_count().to_string());
+                        if ui.button("Load").clicked() {
+                            selected_body = Some(i);
+                        }
+                        ui.end_row();
+                    });
+                });
+                ui.horizontal(|ui| {
+                    if ui.button("Queue all").clicked() {
+                        queue_all = true;
+                    }
+                    if ui.button("Close").clicked() {
+                        close_picker = true;
+                    }
+                });
+            });
+    }
+    if let Some(i) = selected_body {
+        let body = pipe_spec.bodies[i].clone();
+        pipe_spec.jobs.clear();
+        match pipe_spec.set_body(&body) {
+            Ok(_) => { next_state.set(AppMode::Restarting); }
+            Err(e) => { pipe_spec.import_error = Some(e.to_string()); }
+        }
+        close_picker = true;
+    }
+    if (queue_all) {
+        match pipe_spec.queue_all_bodies() {
+            Ok(_) => { next_state.set(AppMode::Restarting); }
+            Err(e) => { pipe_spec.import_error = Some(e.to_string()); }
+        }
+    }
+    if (close_picker) {
+        pipe_spec.show_body_picker = false;
+    }
+
+    let mut add_parts: Option<bool> = None;
+    let mut run_plan = false;
+    let mut save_plan = false;
+    let mut close_nesting = false;
+    if (pipe_spec.show_nesting) {
+        let spec = &mut *pipe_spec;
+        egui::Window::new("Nesting")
+            .collapsible(true)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Kerf");
+                    ui.add(egui::DragValue::new(&mut spec.nesting.kerf).speed(0.1).range(0.0..=50.0));
+                });
+                ui.separator();
+                let mut removed_bar: Option<usize> = None;
+                egui::Grid::new("stock_grid").striped(true).show(ui, |ui| {
+                    ui.label("Stock");
+                    ui.label("Qty (0 any)");
+                    ui.label("");
+                    ui.end_row();
+                    spec.nesting.bars.iter_mut().enumerate().for_each(|(i, bar)| {
+                        ui.add(egui::DragValue::new(&mut bar.length).speed(10.0).range(0.0..=20000.0));
+                        let mut qty = bar.quantity.unwrap_or(0);
+                        if (ui.add(egui::DragValue::new(&mut qty).speed(1.0)).changed()) {
+                            bar.quantity = if (qty == 0) { None } else { Some(qty) };
+                        }
+                        if ui.button("x").clicked() {
+                            removed_bar = Some(i);
+                        }
+                        ui.end_row();
+                    });
+                });
+                if let Some(i) = removed_bar {
+                    spec.nesting.bars.remove(i);
+                }
+                if ui.button("+ stock").clicked() {
+                    spec.nesting.bars.push(StockBar { length: 6000.0, quantity: None });
+                }
+                ui.separator();
+                let mut removed_part: Option<usize> = None;
+                egui::Grid::new("parts_grid").striped(true).show(ui, |ui| {
+                    ui.label("Part");
+                    ui.label("Cut len");
+                    ui.label("Qty");
+                    ui.label("");
+                    ui.end_row();
+                    spec.nesting.parts.iter_mut().enumerate().for_each(|(i, part)| {
+                        ui.label(part.name.as_str());
+                        ui.label(format!("{:.1}", part.cut_length));
+                        ui.add(egui::DragValue::new(&mut part.quantity).speed(1.0));
+                        if ui.button("x").clicked() {
+                            removed_part = Some(i);
+                        }
+                        ui.end_row();
+                    });
+                });
+                if let Some(i) = removed_part {
+                    spec.nesting.parts.remove(i);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Add current").clicked() {
+                        add_parts = Some(false);
+                    }
+                    if ui.button("Add current and queued").clicked() {
+                        add_parts = Some(true);
+                    }
+                    if ui.button("Plan").clicked() {
+                        run_plan = true;
+                    }
+                });
+                if let Some(plan) = &spec.nest_plan {
+                    ui.separator();
+                    egui::Grid::new("plan_grid").striped(true).show(ui, |ui| {
+                        ui.label("Bar");
+                        ui.label("Stock");
+                        ui.label("Cuts");
+                        ui.label("Scrap");
+                        ui.end_row();
+                        plan.bars.iter().enumerate().for_each(|(i, bar)| {
+                            ui.label((i + 1).to_string());
+                            ui.label(format!("{:.0}", bar.stock_length));
+                            ui.label(bar.cuts.iter().map(|c| format!("{} {:.1}", c.part, c.length)).collect::<Vec<String>>().join(" | "));
+                            ui.label(format!("{:.1}", bar.scrap()));
+                            ui.end_row();
+                        });
+                    });
+                    ui.label(format!("Bars {} stock {:.0} mm scrap {:.1} mm", plan.bars.len(), plan.total_stock(), plan.total_scrap()));
+                    if (!plan.unplaced.is_empty()) {
+                        ui.colored_label(egui::Color32::RED, format!("Not placed: {}", plan.unplaced.iter().map(|c| format!("{} {:.1}", c.part, c.length)).collect::<Vec<String>>().join(", ")));
+                    }
+                }
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(spec.nest_plan.is_some(), egui::Button::new("Save CSV")).clicked() {
+                        save_plan = true;
+                    }
+                    if ui.button("Close").clicked() {
+                        close_nesting = true;
+                    }
+                });
+            });
+    }
+    if let Some(with_queue) = add_parts {
+        pipe_spec.add_nest_parts(with_queue);
+    }
+    if (run_plan) {
+        pipe_spec.nest_plan = Some(nest_parts(&pipe_spec.nesting));
+    }
+    if (save_plan) {
+        if let Some(path) = FileDialog::new().add_filter("CSV", &["csv"]).set_file_name("nesting.csv").save_file() {
+            if let Some(plan) = &pipe_spec.nest_plan {
+                if let Err(e) = save_nest_plan(plan, &path) {
+                    pipe_spec.import_error = Some(format!("{:?}: {}", path, e));
+                }
+            }
+        }
+    }
+    if (close_nesting) {
+        pipe_spec.show_nesting = false;
+    }
+
+    let mut close_error = false;
+    if let Some(err) = &pipe_spec.import_error {
+        egui::Window::new("Error")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(err.as_str());
+                if ui.button("OK").clicked() {
+                    close_error = true;
+                }
+            });
+    }
+    if (close_error) {
+        pipe_spec.import_error = None;
+    }
 }
\ No newline at end of file